@@ -1,97 +1,170 @@
+use super::countries::{get_country_info, remove_country, update_country};
+use super::places::{self, Picked, Selection};
 use crate::config::Config;
-use crate::file_ops;
 use crate::geonames;
-use crate::models::{cities::Coordinates, countries::Countries};
-use anyhow::Result;
+use crate::models::cities::{Cities, City, Visit};
+use crate::prompt;
+use anyhow::{Context, Result};
 use colored::Colorize;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::PathBuf;
 
-struct CountryMaps {
-    name_to_iso: HashMap<String, String>,
-    iso_to_name: HashMap<String, String>,
-}
+pub(crate) async fn add_cities(
+    config: &Config,
+    country: &str,
+    names: &[String],
+    selection: &Selection,
+    visit: Option<&Visit>,
+) -> Result<()> {
+    let (country_iso, country_name) = update_country(config, country).await?;
 
-impl CountryMaps {
-    fn new() -> Self {
-        Self {
-            name_to_iso: HashMap::new(),
-            iso_to_name: HashMap::new(),
+    let mut picked = Vec::new();
+
+    let several_sources = config.city_lookup_urls(&country_iso).len() > 1;
+    let get_cities = get_cities(config, names, &country_iso, Some(selection)).await?;
+    for (name, lookup) in get_cities {
+        let no_candidates = lookup.candidates.is_empty();
+        let city = match places::select_place(&name, lookup.candidates, selection)? {
+            Picked::Place(city) => *city,
+            Picked::NotFound => {
+                println!(
+                    "{}",
+                    format!("City '{name}' not found in country '{country_name}'").red()
+                );
+                if no_candidates {
+                    places::print_suggestions(config, &lookup.url, &country_iso, &name, |p| {
+                        p.is_locality()
+                    })
+                    .await?;
+                }
+                continue;
+            }
+            Picked::Skipped => {
+                println!("{}", format!("Skipped city '{name}'").yellow());
+                continue;
+            }
+        };
+        if several_sources {
+            println!(
+                "Resolved '{name}' in {}",
+                lookup.url.rsplit('/').next().unwrap_or(&lookup.url)
+            );
         }
+        picked.push(city);
     }
 
-    fn add_country(&mut self, name: String, iso: String) {
-        let name = name.to_lowercase();
-        let iso = iso.to_lowercase();
-        self.name_to_iso.insert(name.clone(), iso.clone());
-        self.iso_to_name.insert(iso, name);
-    }
+    record_cities(config, &country_iso, &country_name, &picked, visit)
+}
 
-    fn get_iso(&self, name: &str) -> Option<&String> {
-        self.name_to_iso.get(name)
+pub(crate) async fn add_city_at(
+    config: &Config,
+    lat: f64,
+    lon: f64,
+    max_km: Option<f64>,
+    min_population: u64,
+    visit: Option<&Visit>,
+) -> Result<()> {
+    if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) {
+        anyhow::bail!("Coordinates out of range: {lat}, {lon}");
     }
 
-    fn get_name(&self, iso: &str) -> Option<&String> {
-        self.iso_to_name.get(iso)
-    }
+    let Some((mut city, mut distance)) = places::nearest_geoname(
+        config,
+        &config.cities_url(),
+        lat,
+        lon,
+        max_km,
+        min_population,
+    )
+    .await?
+    else {
+        anyhow::bail!("No populated place found near {lat}, {lon}");
+    };
 
-    fn resolve_country(&self, name_or_iso: &str) -> Option<(String, String)> {
-        let name_or_iso = name_or_iso.to_lowercase();
-        if let Some(iso) = self.get_iso(&name_or_iso) {
-            Some((iso.clone(), name_or_iso.to_string()))
-        } else {
-            self.get_name(&name_or_iso)
-                .map(|name| (name_or_iso.to_string(), name.clone()))
+    let country_code = city
+        .country_code
+        .clone()
+        .with_context(|| format!("'{}' has no country code", city.name))?;
+
+    // the global file tells the country, whose own dumps may know a village
+    // closer by
+    for url in config.country_city_urls(&country_code) {
+        if let Some(closer) =
+            places::nearest_geoname(config, &url, lat, lon, Some(distance), min_population).await?
+        {
+            (city, distance) = closer;
         }
     }
-}
+    println!(
+        "Nearest place: {} ({country_code}, {distance:.1} km away)",
+        city.name
+    );
 
-pub(crate) async fn add_cities(config: &Config, country: &str, names: &[String]) -> Result<()> {
-    let (country_iso, country_name) = update_country(config, country).await?;
+    let (country_iso, country_name) = update_country(config, &country_code).await?;
+    record_cities(config, &country_iso, &country_name, &[city], visit)
+}
 
-    let country_file = config
-        .docs
-        .dir
-        .join(&config.docs.cities_folder)
-        .join(format!("{country_name}.json"));
+/// Stores already resolved GeoNames places in the city file of a country.
+pub(crate) fn record_cities(
+    config: &Config,
+    country_iso: &str,
+    country_name: &str,
+    picked: &[geonames::Geoname],
+    visit: Option<&Visit>,
+) -> Result<()> {
+    let country_file = cities_file_path(config, country_iso);
 
-    let mut cities = crate::models::cities::Cities::load_from_file(country_file.to_str().unwrap())
-        .unwrap_or_else(|_| crate::models::cities::Cities::new());
+    let mut cities =
+        Cities::load_from_file(country_file.to_str().unwrap()).unwrap_or_else(|_| Cities::new());
 
     let mut is_changed = false;
 
-    let get_cities = get_cities(config, names, &country_iso).await?;
-    for (name, city) in get_cities {
-        if let Some(city) = city {
-            let coordinates = Coordinates {
-                lat: city.latitude,
-                lon: city.longitude,
-            };
-            if cities.add(city.name.clone(), coordinates) {
-                is_changed = true;
+    for city in picked {
+        // the same place may be stored under an older GeoNames name
+        let stored_name = cities
+            .find_by_geonameid(city.geonameid)
+            .unwrap_or(&city.name)
+            .to_string();
+
+        if cities.add(stored_name.clone(), City::from(city)) {
+            is_changed = true;
+
+            println!(
+                "{}",
+                format!(
+                    "Added city: {} ({}, {})",
+                    city.name, city.latitude, city.longitude
+                )
+                .green()
+            );
+        } else if cities.fill_metadata(&stored_name, City::from(city)) {
+            is_changed = true;
 
+            println!(
+                "{}",
+                format!("Updated city: {} (geonameid {})", city.name, city.geonameid).green()
+            );
+        } else if visit.is_none() {
+            println!(
+                "{}",
+                format!("City '{stored_name}' already exists in country '{country_name}'").yellow()
+            );
+        }
+
+        if let Some(visit) = visit {
+            if cities.add_visit(&stored_name, visit.clone()) {
+                is_changed = true;
                 println!(
                     "{}",
-                    format!(
-                        "Added city: {} ({}, {})",
-                        city.name, city.latitude, city.longitude
-                    )
-                    .green()
+                    format!("Added visit to {stored_name}: {visit}").green()
                 );
             } else {
                 println!(
                     "{}",
-                    format!(
-                        "City '{}' already exists in country '{country_name}'",
-                        city.name
-                    )
-                    .yellow()
+                    format!("Visit {visit} already recorded for city '{stored_name}'").yellow()
                 );
             }
-        } else {
-            println!(
-                "{}",
-                format!("City '{name}' not found in country '{country_name}'").red()
-            );
         }
     }
 
@@ -102,106 +175,218 @@ pub(crate) async fn add_cities(config: &Config, country: &str, names: &[String])
     Ok(())
 }
 
-async fn get_cities(
-    config: &Config,
-    names: &[String],
-    country_iso: &str,
-) -> Result<HashMap<String, Option<geonames::Geoname>>> {
-    let url_str = config.cities_url();
-    let filename = url_str.rsplit('/').next().unwrap_or("cities.zip");
-    let zip_file = config.geonames.download_dir.join(filename);
-
-    file_ops::ensure_file(&url_str, &zip_file).await?;
-    file_ops::unzip_file(
-        zip_file.to_str().unwrap(),
-        config.geonames.download_dir.to_str().unwrap(),
-    )
-    .await?;
-
-    let cities_file = zip_file
-        .with_extension("")
-        .with_extension("txt")
-        .to_str()
-        .unwrap()
-        .to_string();
-
-    let cities = geonames::read_tsv::<geonames::Geoname, _>(&cities_file)?;
-
-    // normalize requested names only once
-    let names_lower: Vec<String> = names.iter().map(|n| n.to_lowercase()).collect();
-    let mut res: HashMap<String, Option<geonames::Geoname>> =
-        names_lower.iter().map(|n| (n.clone(), None)).collect();
-
-    let mut found = 0;
-
-    for city in cities.into_iter().filter(|c| {
-        c.country_code
-            .as_deref()
-            .map_or_else(|| false, |code| code.eq_ignore_ascii_case(country_iso))
-    }) {
-        let keys = std::iter::once(city.name.to_lowercase())
-            .chain(city.asciiname.clone().map(|s| s.to_lowercase()));
-
-        for key in keys {
-            if let Some(entry) = res.get_mut(&key) {
-                *entry = Some(city);
-                found += 1;
-                break;
+pub(crate) async fn remove_cities(config: &Config, country: &str, names: &[String]) -> Result<()> {
+    let (country_iso, country_name) = get_country_info(config, country).await?;
+
+    let country_file = cities_file_path(config, &country_iso);
+    let mut cities = Cities::load_from_file(country_file.to_str().unwrap())
+        .with_context(|| format!("No cities recorded for country '{country_name}'"))?;
+
+    let mut is_changed = false;
+    let mut unmatched = Vec::new();
+
+    for name in names {
+        if let Some(removed) = cities.remove(name) {
+            is_changed = true;
+            println!("{}", format!("Removed city: {removed}").green());
+        } else {
+            unmatched.push(name.clone());
+        }
+    }
+
+    // stored names are GeoNames display names, so look up the remaining ones
+    // to also accept their ascii spelling
+    if !unmatched.is_empty() {
+        for (name, lookup) in get_cities(config, &unmatched, &country_iso, None).await? {
+            if let Some(removed) = lookup
+                .candidates
+                .iter()
+                .find_map(|city| cities.remove(&city.name))
+            {
+                is_changed = true;
+                println!("{}", format!("Removed city: {removed}").green());
+            } else {
+                println!(
+                    "{}",
+                    format!("City '{name}' not found in country '{country_name}'").red()
+                );
             }
         }
+    }
 
-        if found == res.len() {
-            break;
+    if !is_changed {
+        return Ok(());
+    }
+
+    if cities.is_empty()
+        && prompt::confirm(&format!(
+            "No cities left in '{country_name}'. Remove it from visited countries?"
+        ))?
+    {
+        fs::remove_file(&country_file)
+            .with_context(|| format!("Failed to remove {}", country_file.display()))?;
+        remove_country(config, &country_iso, &country_name)?;
+    } else {
+        cities.save_to_file(country_file.to_str().unwrap())?;
+    }
+
+    Ok(())
+}
+
+/// The cities of every visited country, keyed by the stem of their file, the
+/// ISO code.
+pub(crate) fn visited_cities(config: &Config) -> Result<BTreeMap<String, Cities>> {
+    let folder = config.docs.dir.join(&config.docs.cities_folder);
+    let mut visited = BTreeMap::new();
+    let Ok(entries) = fs::read_dir(&folder) else {
+        return Ok(visited);
+    };
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "json")
+            && let Some(stem) = path.file_stem().and_then(|stem| stem.to_str())
+        {
+            let cities = Cities::load_from_file(path.to_str().unwrap())
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            visited.insert(stem.to_string(), cities);
         }
     }
+    Ok(visited)
+}
 
-    Ok(res)
+pub(crate) fn cities_file_path(config: &Config, country_iso: &str) -> PathBuf {
+    config
+        .docs
+        .dir
+        .join(&config.docs.cities_folder)
+        .join(format!("{country_iso}.json"))
 }
 
-async fn update_country(config: &Config, country: &str) -> Result<(String, String)> {
-    let (country_iso, country_name) = get_country_info(config, country).await?;
+/// Candidates for a requested name and the dump they were found in, or for
+/// names found nowhere the last dump searched.
+struct Lookup {
+    url: String,
+    candidates: Vec<geonames::Geoname>,
+}
 
-    let mut counties = Countries::load_from_file(
-        config
-            .docs
-            .dir
-            .join(&config.docs.countries_file)
-            .to_str()
-            .unwrap(),
-    )?;
-
-    if counties.add(&country_name) {
-        counties.save_to_file(
-            config
-                .docs
-                .dir
-                .join(&config.docs.countries_file)
-                .to_str()
-                .unwrap(),
-        )?;
-        println!("Added country: {country_name}");
-    } else {
-        println!("Country '{country_name}' already exists");
+/// Looks the names up in the city sources in order, searching each one only
+/// for the names the ones before did not know. Places outside the `admin1`
+/// region of `selection` do not count as known.
+async fn get_cities(
+    config: &Config,
+    names: &[String],
+    country_iso: &str,
+    selection: Option<&Selection>,
+) -> Result<HashMap<String, Lookup>> {
+    let mut found = HashMap::new();
+    let mut missing = names.to_vec();
+    for url in config.city_lookup_urls(country_iso) {
+        if missing.is_empty() {
+            break;
+        }
+        let results = places::find_geonames(config, &url, country_iso, &missing).await?;
+        missing.clear();
+        for (name, mut candidates) in results {
+            candidates.retain(|c| c.is_locality() && selection.is_none_or(|s| s.admits(c)));
+            if candidates.is_empty() {
+                missing.push(name.clone());
+            }
+            let url = url.clone();
+            found.insert(name, Lookup { url, candidates });
+        }
     }
-    Ok((country_iso, country_name))
+    Ok(found)
 }
 
-async fn get_country_info(config: &Config, country: &str) -> Result<(String, String)> {
-    let url_str = config.country_info_url();
-    let filename = url_str.rsplit('/').next().unwrap_or("countryInfo.txt");
-    let output_path = config.geonames.download_dir.join(filename);
-    file_ops::ensure_file(&url_str, &output_path).await?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geonames::{self, Geoname};
+    use std::env;
+
+    const VALENCIA: &str = "2509954\tValencia\tValencia\t\t39.46975\t-0.37739\tP\tPPLA2\tES\t\t60\tV\t46250\t\t814208\t\t15\tEurope/Madrid\t2022-03-09";
+    const VALENCIA_VILLAGE: &str = "6361214\tValencia\tValencia\t\t42.16472\t-5.81217\tP\tPPL\tES\t\t55\tLE\t24200\t\t0\t\t763\tEurope/Madrid\t2012-03-04";
+    const BENAOJAN: &str = "2521390\tBenaoján\tBenaojan\t\t36.71874\t-5.25446\tP\tPPL\tES\t\t51\tMA\t29370\t\t1520\t\t556\tEurope/Madrid\t2012-03-04";
+
+    #[tokio::test]
+    async fn test_get_cities_falls_back_per_name() -> Result<()> {
+        let dir = env::temp_dir().join("waymarks_get_cities_test");
+        if dir.exists() {
+            fs::remove_dir_all(&dir)?;
+        }
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join("cities.txt"), format!("{VALENCIA}\n"))?;
+        fs::write(
+            dir.join("ES.txt"),
+            format!("{VALENCIA}\n{VALENCIA_VILLAGE}\n{BENAOJAN}\n"),
+        )?;
+        let config: Config = toml::from_str(&format!(
+            r#"
+            [geonames]
+            base_url = "https://example.org/dump/"
+            country_info_file = "countryInfo.txt"
+            cities_file = "cities.txt"
+            cities_sources = ["cities.txt", "{{country}}.txt"]
+            download_dir = {:?}
+
+            [docs]
+            dir = "docs"
+            countries_file = "countries.json"
+            cities_folder = "cities"
+            "#,
+            dir.to_str().unwrap()
+        ))?;
+        let names = ["Valencia".to_string(), "Benaojan".to_string()];
+
+        // found in the first source, the second is only searched for the rest
+        let found = get_cities(&config, &names, "ES", None).await?;
+        assert!(found["valencia"].url.ends_with("/cities.txt"));
+        assert_eq!(found["valencia"].candidates.len(), 1);
+        assert!(found["benaojan"].url.ends_with("/ES.txt"));
+        assert_eq!(found["benaojan"].candidates[0].geonameid, 2521390);
 
-    let countries = geonames::read_tsv::<geonames::CountryInfo, _>(&output_path)?;
-    let mut country_maps = CountryMaps::new();
+        // a place in another region does not stop the search
+        let selection = Selection {
+            admin1: Some("55".to_string()),
+            near: None,
+            interactive: false,
+        };
+        let found = get_cities(&config, &names[..1], "ES", Some(&selection)).await?;
+        assert!(found["valencia"].url.ends_with("/ES.txt"));
+        let ids: Vec<i64> = found["valencia"]
+            .candidates
+            .iter()
+            .map(|c| c.geonameid)
+            .collect();
+        assert_eq!(ids, [6361214]);
 
-    for country in countries {
-        country_maps.add_country(country.country.clone(), country.iso.clone());
+        fs::remove_dir_all(&dir)?;
+        Ok(())
     }
 
-    if let Some((iso, name)) = country_maps.resolve_country(country) {
-        Ok((iso, name.replace(' ', "_")))
-    } else {
-        Err(anyhow::anyhow!("Invalid country name or ISO: {}", country))
+    #[test]
+    fn test_select_place_outcomes() -> Result<()> {
+        let candidates = || -> Result<Vec<Geoname>> {
+            let tsv = format!("{VALENCIA}\n{VALENCIA_VILLAGE}\n");
+            geonames::records(tsv.as_bytes()).collect()
+        };
+        let mut selection = Selection {
+            admin1: None,
+            near: None,
+            interactive: false,
+        };
+
+        let Picked::Place(city) = places::select_place("Valencia", candidates()?, &selection)?
+        else {
+            panic!("expected a place");
+        };
+        assert_eq!(city.geonameid, 2509954);
+
+        selection.admin1 = Some("MD".to_string());
+        assert!(matches!(
+            places::select_place("Valencia", candidates()?, &selection)?,
+            Picked::NotFound
+        ));
+        Ok(())
     }
 }
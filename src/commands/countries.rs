@@ -0,0 +1,642 @@
+use super::cities::cities_file_path;
+use super::places;
+use crate::config::Config;
+use crate::file_ops;
+use crate::geonames::{self, CountryInfo, FeatureClass, RecordFilter};
+use crate::models::cities::{Cities, Visit};
+use crate::models::countries::{Countries, Country};
+use crate::models::summits::Summits;
+use crate::{prompt, text};
+use anyhow::{Context, Result};
+use colored::Colorize;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use tokio::task;
+
+/// Folder of the docs with one GeoJSON outline per country for the map.
+const OUTLINES_FOLDER: &str = "countries-geo";
+
+/// Common names GeoNames does not list countries under, with their ISO codes.
+const BUILTIN_ALIASES: &[(&str, &str)] = &[
+    ("USA", "US"),
+    ("United States of America", "US"),
+    ("America", "US"),
+    ("UK", "GB"),
+    ("Great Britain", "GB"),
+    ("Britain", "GB"),
+    ("England", "GB"),
+    ("Scotland", "GB"),
+    ("Wales", "GB"),
+    ("Northern Ireland", "GB"),
+    ("Holland", "NL"),
+    ("Netherlands", "NL"),
+    ("Czech Republic", "CZ"),
+    ("Ivory Coast", "CI"),
+    ("Cote d'Ivoire", "CI"),
+    ("UAE", "AE"),
+    ("Burma", "MM"),
+    ("Swaziland", "SZ"),
+    ("Macedonia", "MK"),
+    ("Cape Verde", "CV"),
+    ("East Timor", "TL"),
+    ("Vatican City", "VA"),
+    ("Holy See", "VA"),
+    ("Turkey", "TR"),
+    ("South Korea", "KR"),
+    ("North Korea", "KP"),
+    ("DRC", "CD"),
+    ("Congo-Kinshasa", "CD"),
+    ("Congo-Brazzaville", "CG"),
+    ("Bosnia", "BA"),
+    ("Russian Federation", "RU"),
+];
+
+/// Cache of the names [`localized_names`] found, in the download directory.
+const LOCALIZED_NAMES_FILE: &str = "countryNames.tsv";
+
+/// Suggestions offered for a country name that resolves to nothing.
+const SUGGESTIONS: usize = 3;
+
+pub(crate) struct CountryMaps {
+    /// Names, ISO codes and aliases, compared as [`lookup_key`] makes them, to
+    /// ISO alpha-2 codes.
+    keys: HashMap<String, String>,
+    /// Localized names several countries go by, like "Congo", which resolve
+    /// to none of them but are offered as suggestions.
+    ambiguous: HashMap<String, BTreeSet<String>>,
+    iso_to_name: HashMap<String, String>,
+}
+
+impl CountryMaps {
+    /// Maps the GeoNames names, the ISO alpha-2, alpha-3 and numeric codes and
+    /// the built-in aliases of `countries`.
+    pub(crate) fn new(countries: &[CountryInfo]) -> Self {
+        let mut maps = Self {
+            keys: HashMap::new(),
+            ambiguous: HashMap::new(),
+            iso_to_name: countries
+                .iter()
+                .map(|country| (country.iso.to_uppercase(), country.country.clone()))
+                .collect(),
+        };
+        for (alias, iso) in BUILTIN_ALIASES {
+            maps.add_alias(alias, iso, false);
+        }
+        for country in countries {
+            maps.add_country(country);
+        }
+        maps
+    }
+
+    fn add_country(&mut self, country: &CountryInfo) {
+        let iso = country.iso.to_uppercase();
+        let mut keys = vec![
+            country.country.as_str(),
+            country.iso.as_str(),
+            country.iso3.as_str(),
+            country.iso_numeric.as_str(),
+        ];
+        // numeric codes are written without their leading zeros too, 20 for 020
+        let numeric = country.iso_numeric.trim_start_matches('0');
+        if !numeric.is_empty() {
+            keys.push(numeric);
+        }
+        for key in keys.into_iter().filter(|key| !key.is_empty()) {
+            self.keys.insert(lookup_key(key), iso.clone());
+        }
+    }
+
+    /// Makes `alias` resolve to the country with ISO code `iso`. With
+    /// `replace` false, names that already resolve are left alone. Returns
+    /// false for an unknown ISO code.
+    pub(crate) fn add_alias(&mut self, alias: &str, iso: &str, replace: bool) -> bool {
+        let iso = iso.to_uppercase();
+        if !self.iso_to_name.contains_key(&iso) {
+            return false;
+        }
+        let key = lookup_key(alias);
+        if replace || !self.keys.contains_key(&key) {
+            self.keys.insert(key, iso);
+        }
+        true
+    }
+
+    /// Adds localized names, as (ISO code, name) pairs, that do not resolve yet.
+    /// A name listed for several countries is left ambiguous instead of
+    /// resolving to whichever comes first.
+    fn add_localized_names(&mut self, names: &[(String, String)]) {
+        let mut by_key: HashMap<String, BTreeSet<String>> = HashMap::new();
+        for (iso, name) in names {
+            let iso = iso.to_uppercase();
+            if self.iso_to_name.contains_key(&iso) {
+                by_key.entry(lookup_key(name)).or_default().insert(iso);
+            }
+        }
+        for (key, isos) in by_key {
+            if self.keys.contains_key(&key) {
+                continue;
+            }
+            if isos.len() == 1 {
+                self.keys.insert(key, isos.into_iter().next().unwrap());
+            } else {
+                self.ambiguous.insert(key, isos);
+            }
+        }
+    }
+
+    /// ISO code and display name of a country given by name, alias or ISO code.
+    pub(crate) fn resolve_country(&self, name_or_code: &str) -> Option<(String, String)> {
+        let iso = self.keys.get(&lookup_key(name_or_code))?;
+        let name = self.iso_to_name.get(iso)?;
+        Some((iso.clone(), name.clone()))
+    }
+
+    /// Display names of the countries with a name or alias close to `name`,
+    /// closest first, for a misspelt country.
+    pub(crate) fn suggest(&self, name: &str, limit: usize) -> Vec<String> {
+        let key = lookup_key(name);
+        let max_distance = (key.chars().count() / 3).max(1);
+
+        let mut distances: HashMap<&str, usize> = HashMap::new();
+        // codes are too short for edit distances to mean anything
+        let ambiguous = self
+            .ambiguous
+            .iter()
+            .flat_map(|(key, isos)| isos.iter().map(move |iso| (key, iso)));
+        for (candidate, iso) in self
+            .keys
+            .iter()
+            .chain(ambiguous)
+            .filter(|(k, _)| k.chars().count() > 3)
+        {
+            let distance = text::levenshtein(&key, candidate);
+            if distance <= max_distance {
+                let best = distances.entry(iso).or_insert(distance);
+                *best = (*best).min(distance);
+            }
+        }
+        let mut closest: Vec<(usize, &String)> = distances
+            .into_iter()
+            .filter_map(|(iso, distance)| Some((distance, self.iso_to_name.get(iso)?)))
+            .collect();
+        closest.sort();
+        closest
+            .into_iter()
+            .take(limit)
+            .map(|(_, name)| name.clone())
+            .collect()
+    }
+}
+
+/// Country maps with the aliases of the config on top of the built-in ones.
+pub(crate) fn country_maps(config: &Config, countries: &[CountryInfo]) -> CountryMaps {
+    let mut maps = CountryMaps::new(countries);
+    for (alias, iso) in &config.country_aliases {
+        if !maps.add_alias(alias, iso, true) {
+            eprintln!(
+                "{}",
+                format!("Ignoring alias '{alias}': no country has ISO code {iso}").yellow()
+            );
+        }
+    }
+    maps
+}
+
+/// Key names and codes are compared under: without accents, lowercase, with
+/// spaces for the underscores of the names countries were stored under before
+/// ISO codes.
+fn lookup_key(name: &str) -> String {
+    geonames::normalize_name(&name.replace('_', " "))
+}
+
+pub(crate) async fn update_country(config: &Config, country: &str) -> Result<(String, String)> {
+    let (country_iso, country_name) = get_country_info(config, country).await?;
+
+    let countries_file = config.docs.dir.join(&config.docs.countries_file);
+    let mut countries = Countries::load_from_file(countries_file.to_str().unwrap())?;
+
+    if record_country(config, &mut countries, &country_iso, &country_name, None) {
+        countries.save_to_file(countries_file.to_str().unwrap())?;
+    }
+    Ok((country_iso, country_name))
+}
+
+/// Adds countries by name, alias or ISO code, for those visited without
+/// stopping in a city, like ones crossed on the way.
+pub(crate) async fn add_countries(
+    config: &Config,
+    names: &[String],
+    visit: Option<&Visit>,
+) -> Result<()> {
+    let infos = load_country_info(config).await?;
+    let mut maps = country_maps(config, &infos);
+    let countries_file = config.docs.dir.join(&config.docs.countries_file);
+    let mut countries = Countries::load_from_file(countries_file.to_str().unwrap())?;
+
+    let mut is_changed = false;
+    for name in names {
+        match resolve(config, &infos, &mut maps, name).await {
+            Ok((iso, display_name)) => {
+                is_changed |= record_country(config, &mut countries, &iso, &display_name, visit);
+            }
+            Err(err) => println!("{}", err.to_string().red()),
+        }
+    }
+
+    if is_changed {
+        countries.save_to_file(countries_file.to_str().unwrap())?;
+    }
+    Ok(())
+}
+
+/// Adds a country and the visit to it, returning whether anything changed.
+fn record_country(
+    config: &Config,
+    countries: &mut Countries,
+    iso: &str,
+    name: &str,
+    visit: Option<&Visit>,
+) -> bool {
+    let country = Country {
+        name: name.to_string(),
+        outline: outline_for(config, [country_key(name).as_str()]),
+        visits: Vec::new(),
+    };
+    let mut is_changed = countries.add(iso, country);
+    if is_changed {
+        println!("Added country: {name}");
+    } else if visit.is_none() {
+        println!("Country '{name}' already exists");
+    }
+
+    if let Some(visit) = visit {
+        if countries.add_visit(iso, visit.clone()) {
+            is_changed = true;
+            println!("{}", format!("Added visit to {name}: {visit}").green());
+        } else {
+            println!(
+                "{}",
+                format!("Visit {visit} already recorded for country '{name}'").yellow()
+            );
+        }
+    }
+    is_changed
+}
+
+/// Removes countries by name, alias or ISO code. Cities recorded in one are
+/// removed with it after confirmation, otherwise the country is kept.
+pub(crate) async fn remove_countries(config: &Config, names: &[String]) -> Result<()> {
+    let infos = load_country_info(config).await?;
+    let mut maps = country_maps(config, &infos);
+    let countries_file = config.docs.dir.join(&config.docs.countries_file);
+    let mut countries = Countries::load_from_file(countries_file.to_str().unwrap())?;
+
+    let summits_file = config.docs.dir.join(&config.docs.summits_file);
+    let summits = if summits_file.exists() {
+        Summits::load_from_file(summits_file.to_str().unwrap())?
+    } else {
+        Summits::new()
+    };
+
+    for name in names {
+        let (iso, display_name) = match resolve(config, &infos, &mut maps, name).await {
+            Ok(found) => found,
+            Err(err) => {
+                println!("{}", err.to_string().red());
+                continue;
+            }
+        };
+        if !countries.contains(&iso) {
+            println!(
+                "{}",
+                format!("Country '{display_name}' is not in the visited list").red()
+            );
+            continue;
+        }
+
+        let cities_file = cities_file_path(config, &iso);
+        if cities_file.exists() {
+            let count = Cities::load_from_file(cities_file.to_str().unwrap())
+                .map_or(0, |cities| cities.cities.len());
+            if !prompt::confirm(&format!(
+                "Cities are recorded in '{display_name}' ({count}). Remove them too?"
+            ))? {
+                println!("{}", format!("Kept country '{display_name}'").yellow());
+                continue;
+            }
+        }
+        // Saved before the city file goes, so an interrupted run never leaves
+        // a listed country without its cities.
+        countries.remove(&iso);
+        countries.save_to_file(countries_file.to_str().unwrap())?;
+        if cities_file.exists() {
+            fs::remove_file(&cities_file)
+                .with_context(|| format!("Failed to remove {}", cities_file.display()))?;
+        }
+        println!("{}", format!("Removed country: {display_name}").green());
+
+        let summit_count = summits
+            .features
+            .iter()
+            .filter(|feature| {
+                feature
+                    .properties
+                    .country_code
+                    .as_deref()
+                    .is_some_and(|code| code.eq_ignore_ascii_case(&iso))
+            })
+            .count();
+        if summit_count > 0 {
+            println!(
+                "{}",
+                format!(
+                    "{summit_count} summit(s) are still recorded in '{display_name}', remove them from {} if needed",
+                    config.docs.summits_file
+                )
+                .yellow()
+            );
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn remove_country(config: &Config, country_iso: &str, country_name: &str) -> Result<()> {
+    let countries_file = config.docs.dir.join(&config.docs.countries_file);
+    let mut countries = Countries::load_from_file(countries_file.to_str().unwrap())?;
+
+    if countries.remove(country_iso) {
+        countries.save_to_file(countries_file.to_str().unwrap())?;
+        println!("Removed country: {country_name}");
+    } else {
+        println!("Country '{country_name}' is not in the visited list");
+    }
+    Ok(())
+}
+
+/// Resolves a country name, alias or ISO code to the ISO alpha-2 code and the
+/// display name.
+pub(crate) async fn get_country_info(config: &Config, country: &str) -> Result<(String, String)> {
+    let countries = load_country_info(config).await?;
+    let mut maps = country_maps(config, &countries);
+    resolve(config, &countries, &mut maps, country).await
+}
+
+/// Resolves `country` through `maps`, adding the localized names to them when
+/// it is none of the names and codes they know.
+async fn resolve(
+    config: &Config,
+    countries: &[CountryInfo],
+    maps: &mut CountryMaps,
+    country: &str,
+) -> Result<(String, String)> {
+    if let Some(found) = maps.resolve_country(country) {
+        return Ok(found);
+    }
+
+    maps.add_localized_names(&localized_names(config, countries).await?);
+    if let Some(found) = maps.resolve_country(country) {
+        return Ok(found);
+    }
+
+    let suggestions = maps.suggest(country, SUGGESTIONS);
+    if suggestions.is_empty() {
+        anyhow::bail!("Invalid country name or ISO: {country}");
+    }
+    anyhow::bail!(
+        "Invalid country name or ISO: {country}, did you mean {}?",
+        suggestions.join(", ")
+    )
+}
+
+/// Names of countries in other languages and spellings, like "Deutschland"
+/// or "Suomi", as (ISO code, name) pairs. They are the alternate names of the
+/// country rows in the GeoNames dumps downloaded so far: per-country dumps and
+/// allCountries.zip have them, cities500.zip does not. Reading a large dump
+/// takes a while, so they are cached until the dumps change.
+async fn localized_names(
+    config: &Config,
+    countries: &[CountryInfo],
+) -> Result<Vec<(String, String)>> {
+    let mut urls = vec![config.cities_url()];
+    let patterns = config.geonames.cities_sources.iter();
+    for pattern in patterns.chain([&config.geonames.summits_file]) {
+        urls.extend(places::downloaded_dumps(config, pattern)?);
+    }
+    urls.sort();
+    urls.dedup();
+    let dumps: Vec<PathBuf> = urls
+        .iter()
+        .map(|url| places::dump_source(config, url))
+        .filter(|path| path.exists())
+        .collect();
+    let country_ids: HashMap<String, String> = countries
+        .iter()
+        .filter_map(|country| Some((country.geonameid?.to_string(), country.iso.clone())))
+        .collect();
+    let cache = config.geonames.download_dir.join(LOCALIZED_NAMES_FILE);
+
+    task::spawn_blocking(move || read_localized_names(&dumps, &country_ids, &cache)).await?
+}
+
+fn read_localized_names(
+    dumps: &[PathBuf],
+    country_ids: &HashMap<String, String>,
+    cache: &Path,
+) -> Result<Vec<(String, String)>> {
+    if dumps.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut stamp = String::from("#");
+    for dump in dumps {
+        let meta = fs::metadata(dump)?;
+        let modified = meta.modified()?.duration_since(UNIX_EPOCH)?.as_secs();
+        let name = dump.file_name().unwrap_or_default().to_string_lossy();
+        write!(stamp, " {name}:{}:{modified}", meta.len())?;
+    }
+
+    if let Ok(content) = fs::read_to_string(cache)
+        && content.lines().next() == Some(stamp.as_str())
+    {
+        return Ok(content
+            .lines()
+            .skip(1)
+            .filter_map(|line| line.split_once('\t'))
+            .map(|(iso, name)| (iso.to_string(), name.to_string()))
+            .collect());
+    }
+
+    let filter = RecordFilter {
+        feature_classes: vec![FeatureClass::A],
+    };
+    let mut names = BTreeSet::new();
+    for dump in dumps {
+        for row in geonames::rows(geonames::open_dump(dump)?, &filter) {
+            let row = row?;
+            let columns = row.columns();
+            let Some(iso) = country_ids.get(columns[0]) else {
+                continue;
+            };
+            let alternate_names = columns.get(3).copied().unwrap_or_default().split(',');
+            for name in columns[1..3].iter().copied().chain(alternate_names) {
+                if !name.is_empty() {
+                    names.insert((iso.clone(), name.to_string()));
+                }
+            }
+        }
+    }
+
+    let mut content = stamp;
+    for (iso, name) in &names {
+        write!(content, "\n{iso}\t{name}")?;
+    }
+    fs::write(cache, content + "\n")?;
+    Ok(names.into_iter().collect())
+}
+
+/// Every row of countryInfo.txt, downloading it first if needed.
+pub(crate) async fn load_country_info(config: &Config) -> Result<Vec<CountryInfo>> {
+    let url_str = config.country_info_url();
+    let filename = url_str.rsplit('/').next().unwrap_or("countryInfo.txt");
+    let output_path = config.geonames.download_dir.join(filename);
+    file_ops::ensure_file(&url_str, &output_path, &config.download_policy(&url_str)).await?;
+
+    geonames::read_tsv(&output_path)
+}
+
+/// Lowercase, underscored form of a country name, which countries were stored
+/// under before ISO codes and which the map outlines are named after, e.g.
+/// `united_arab_emirates`.
+pub(crate) fn country_key(name: &str) -> String {
+    name.to_lowercase().replace(' ', "_")
+}
+
+/// The first of `candidates` the docs have a map outline for.
+pub(crate) fn outline_for<'a, I>(config: &Config, candidates: I) -> Option<String>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let folder = config.docs.dir.join(OUTLINES_FOLDER);
+    candidates
+        .into_iter()
+        .find(|stem| folder.join(format!("{stem}.json")).exists())
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn sample_countries() -> Result<Vec<CountryInfo>> {
+        let path =
+            PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data/countryInfo_sample.txt");
+        geonames::read_tsv(&path)
+    }
+
+    #[test]
+    fn test_resolve_country() -> Result<()> {
+        let mut maps = CountryMaps::new(&sample_countries()?);
+        let iso = |maps: &CountryMaps, name| maps.resolve_country(name).map(|(iso, _)| iso);
+
+        assert_eq!(
+            maps.resolve_country("united_arab_emirates"),
+            Some(("AE".to_string(), "United Arab Emirates".to_string()))
+        );
+        assert_eq!(iso(&maps, "ad").as_deref(), Some("AD"));
+        assert_eq!(iso(&maps, "AFG").as_deref(), Some("AF"));
+        assert_eq!(iso(&maps, "020").as_deref(), Some("AD"));
+        assert_eq!(iso(&maps, "20").as_deref(), Some("AD"));
+        assert_eq!(iso(&maps, "usa").as_deref(), Some("US"));
+        assert_eq!(iso(&maps, "840").as_deref(), Some("US"));
+        assert_eq!(
+            iso(&maps, "United States of America").as_deref(),
+            Some("US")
+        );
+        assert_eq!(iso(&maps, "Congo-Brazzaville").as_deref(), Some("CG"));
+        assert_eq!(iso(&maps, "UAE").as_deref(), Some("AE"));
+        // aliases of countries missing from countryInfo.txt resolve to nothing
+        assert_eq!(iso(&maps, "Burma"), None);
+
+        assert!(maps.add_alias("Andorre", "ad", false));
+        assert!(!maps.add_alias("Atlantis", "XX", false));
+        assert_eq!(iso(&maps, "andorre").as_deref(), Some("AD"));
+        // names keep resolving to their country unless replaced
+        assert!(maps.add_alias("Andorra", "AE", false));
+        assert_eq!(iso(&maps, "Andorra").as_deref(), Some("AD"));
+        assert!(maps.add_alias("Andorra", "AE", true));
+        assert_eq!(iso(&maps, "Andorra").as_deref(), Some("AE"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_suggest_country() -> Result<()> {
+        let maps = CountryMaps::new(&sample_countries()?);
+        assert_eq!(maps.suggest("Andora", SUGGESTIONS), ["Andorra"]);
+        assert_eq!(maps.suggest("Afganistan", SUGGESTIONS), ["Afghanistan"]);
+        assert_eq!(maps.suggest("United Stats", SUGGESTIONS), ["United States"]);
+        assert!(maps.suggest("Atlantis", SUGGESTIONS).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_ambiguous_localized_names() -> Result<()> {
+        let mut maps = CountryMaps::new(&sample_countries()?);
+        let names = [
+            ("CD", "Congo"),
+            ("CD", "Kongo"),
+            ("CD", "République démocratique du Congo"),
+            ("CG", "Congo"),
+            ("CG", "République du Congo"),
+            ("AD", "Andorre"),
+            // GeoNames names win over localized ones
+            ("AD", "Andorra"),
+            ("AE", "Andorra"),
+        ]
+        .map(|(iso, name)| (iso.to_string(), name.to_string()));
+        maps.add_localized_names(&names);
+        let iso = |name| maps.resolve_country(name).map(|(iso, _)| iso);
+
+        assert_eq!(iso("Congo"), None);
+        assert_eq!(iso("Kongo").as_deref(), Some("CD"));
+        assert_eq!(iso("republique du congo").as_deref(), Some("CG"));
+        assert_eq!(iso("Andorre").as_deref(), Some("AD"));
+        assert_eq!(iso("Andorra").as_deref(), Some("AD"));
+        assert_eq!(
+            maps.suggest("Congo", SUGGESTIONS),
+            ["Congo Republic", "DR Congo"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_localized_names() -> Result<()> {
+        let dir = env::temp_dir().join("test_localized_names");
+        fs::create_dir_all(&dir)?;
+        let dump = dir.join("AD.txt");
+        let cache = dir.join(LOCALIZED_NAMES_FILE);
+        let _ = fs::remove_file(&cache);
+        fs::write(
+            &dump,
+            "3041565\tPrincipality of Andorra\tPrincipality of Andorra\tAndorre,Andorra,Andorre\t42.55\t1.58\tA\tPCLI\tAD\t\t00\t\t\t\t77006\t\t1266\tEurope/Andorra\t2024-01-01\n\
+             3041563\tAndorra la Vella\tAndorra la Vella\tAndorre-la-Vieille\t42.50\t1.52\tP\tPPLC\tAD\t\t07\t\t\t\t20430\t\t1037\tEurope/Andorra\t2024-01-01\n",
+        )?;
+        let ids = HashMap::from([("3041565".to_string(), "AD".to_string())]);
+
+        let names = read_localized_names(std::slice::from_ref(&dump), &ids, &cache)?;
+        let names: Vec<&str> = names.iter().map(|(_, name)| name.as_str()).collect();
+        assert_eq!(names, ["Andorra", "Andorre", "Principality of Andorra"]);
+        assert!(cache.exists());
+
+        // served from the cache while the dump is unchanged
+        let cached = fs::read_to_string(&cache)?.replace("Andorre", "Andòrra");
+        fs::write(&cache, cached)?;
+        let names = read_localized_names(&[dump], &ids, &cache)?;
+        assert!(names.contains(&("AD".to_string(), "Andòrra".to_string())));
+
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,251 @@
+use super::cities::visited_cities;
+use super::places;
+use crate::config::Config;
+use crate::deltas::{self, Delta};
+use crate::file_ops::{self, DownloadPolicy};
+use crate::geo::haversine_km;
+use crate::index;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Days, NaiveDate, Utc};
+use colored::Colorize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use tokio::task;
+
+/// GeoNames keeps the daily files for about a month; further behind, the full
+/// dump has to be downloaded again.
+const MAX_DAILY_UPDATES: u64 = 30;
+
+/// Refreshes every GeoNames file downloaded so far, fetching only those the
+/// server reports as changed.
+pub(crate) async fn update_data(config: &Config) -> Result<()> {
+    let country_info = config.country_info_url();
+    let filename = file_name(&country_info);
+    let mut updated = 0;
+    if file_ops::refresh_file(
+        &country_info,
+        config.geonames.download_dir.join(filename),
+        &config.download_policy(&country_info),
+    )
+    .await?
+    {
+        updated += 1;
+    }
+
+    // only dumps already downloaded are refreshed, the others are fetched when
+    // first needed
+    let mut dumps = Vec::new();
+    let patterns = config.geonames.cities_sources.iter();
+    for pattern in patterns.chain([&config.geonames.cities_file, &config.geonames.summits_file]) {
+        dumps.extend(places::downloaded_dumps(config, pattern)?);
+    }
+    dumps.sort();
+    dumps.dedup();
+    for url in &dumps {
+        if places::refresh_dump(config, url).await? {
+            updated += 1;
+        }
+    }
+
+    let checked = dumps.len() + 1;
+    if updated == 0 {
+        println!("{}", format!("All {checked} files are up to date").green());
+    } else {
+        println!(
+            "{}",
+            format!("Updated {updated} of {checked} files").green()
+        );
+    }
+    Ok(())
+}
+
+/// Applies the modifications and deletions GeoNames published each day since
+/// the cities dump was downloaded, or since `since`, to a copy of the dump.
+pub(crate) async fn apply_daily_updates(config: &Config, since: Option<NaiveDate>) -> Result<()> {
+    let url = config.cities_url();
+    let mut source = places::prepare_dump(config, &url).await?;
+    let patched = places::patched_dump_path(config, &url);
+    let state_file = patched.with_extension("deltas");
+
+    // the files for a day are published early the next day
+    let last_day = Utc::now().date_naive() - Days::new(1);
+    let first_day = match since {
+        Some(day) => day,
+        None => next_update_day(&source, &state_file)?,
+    };
+    if first_day > last_day {
+        println!("{}", "Daily updates are applied up to yesterday".green());
+        return Ok(());
+    }
+    if (last_day - first_day).num_days() as u64 >= MAX_DAILY_UPDATES {
+        anyhow::bail!(
+            "Data is more than {MAX_DAILY_UPDATES} days behind, run update-data to download the full dump"
+        );
+    }
+
+    let min_population = dump_min_population(&url);
+    let visited = visited_by_geonameid(config)?;
+    let updates_dir = config.geonames.download_dir.join("updates");
+
+    for day in first_day.iter_days().take_while(|day| *day <= last_day) {
+        let modifications_url = config.modifications_url(day);
+        let deletes_url = config.deletes_url(day);
+        let modifications = updates_dir.join(file_name(&modifications_url));
+        let deletes = updates_dir.join(file_name(&deletes_url));
+        file_ops::ensure_file(
+            &modifications_url,
+            &modifications,
+            &DownloadPolicy::default(),
+        )
+        .await?;
+        file_ops::ensure_file(&deletes_url, &deletes, &DownloadPolicy::default()).await?;
+
+        let (from, target) = (source.clone(), patched.clone());
+        let (delta, applied) = task::spawn_blocking(move || -> Result<_> {
+            let delta = Delta::read(&modifications, &deletes)?;
+            let applied = delta.apply(&from, &target, |record| {
+                deltas::is_dump_city(record, min_population)
+            })?;
+            fs::remove_file(modifications)?;
+            fs::remove_file(deletes)?;
+            Ok((delta, applied))
+        })
+        .await??;
+
+        source = patched.clone();
+        index::remove_index(&patched)?;
+        fs::write(&state_file, format!("{day} {}", file_stamp(&patched)?))?;
+        println!(
+            "Applied updates of {day}: {} changed, {} added, {} deleted",
+            applied.updated, applied.added, applied.deleted
+        );
+        report_visited(&delta, &visited);
+    }
+
+    Ok(())
+}
+
+/// First day whose updates are not in `source` yet. The state file only counts
+/// while the copy it was written for is in use; for a freshly downloaded dump,
+/// start the day before the download to cover the time between GeoNames
+/// exporting the dump and us fetching it.
+fn next_update_day(source: &Path, state_file: &Path) -> Result<NaiveDate> {
+    let stamp = file_stamp(source)?;
+    if let Ok(state) = fs::read_to_string(state_file)
+        && let Some((day, applied_to)) = state.trim().split_once(' ')
+        && applied_to == stamp
+    {
+        let day: NaiveDate = day
+            .parse()
+            .with_context(|| format!("Invalid state in {}", state_file.display()))?;
+        return Ok(day + Days::new(1));
+    }
+
+    let downloaded: DateTime<Utc> = fs::metadata(source)?.modified()?.into();
+    Ok(downloaded.date_naive() - Days::new(1))
+}
+
+fn file_stamp(path: &Path) -> Result<String> {
+    let metadata = fs::metadata(path)?;
+    let modified: DateTime<Utc> = metadata.modified()?.into();
+    Ok(format!(
+        "{}:{}",
+        metadata.len(),
+        modified.timestamp_nanos_opt().unwrap_or_default()
+    ))
+}
+
+/// The population limit in a `cities<N>.zip` dump name, 0 for other dumps.
+fn dump_min_population(url: &str) -> u64 {
+    file_name(url)
+        .strip_prefix("cities")
+        .and_then(|rest| rest.split('.').next())
+        .and_then(|limit| limit.parse().ok())
+        .unwrap_or(0)
+}
+
+fn file_name(url: &str) -> &str {
+    url.rsplit('/').next().unwrap_or(url)
+}
+
+struct VisitedCity {
+    country: String,
+    name: String,
+    lat: f64,
+    lon: f64,
+    population: Option<u64>,
+}
+
+fn visited_by_geonameid(config: &Config) -> Result<HashMap<i64, VisitedCity>> {
+    let mut visited = HashMap::new();
+    for (country, cities) in visited_cities(config)? {
+        for (name, city) in cities.cities {
+            if let Some(id) = city.geonameid {
+                visited.insert(
+                    id,
+                    VisitedCity {
+                        country: country.clone(),
+                        name,
+                        lat: city.lat,
+                        lon: city.lon,
+                        population: city.population,
+                    },
+                );
+            }
+        }
+    }
+    Ok(visited)
+}
+
+/// Tells which visited cities GeoNames changed or deleted, so the stored
+/// copies can be checked.
+fn report_visited(delta: &Delta, visited: &HashMap<i64, VisitedCity>) {
+    for (id, (record, _)) in &delta.modified {
+        let Some(city) = visited.get(id) else {
+            continue;
+        };
+        let mut changes = Vec::new();
+        if record.name != city.name {
+            changes.push(format!("renamed to {}", record.name));
+        }
+        let moved = haversine_km(city.lat, city.lon, record.latitude, record.longitude);
+        if moved >= 0.01 {
+            changes.push(format!("moved {moved:.2} km"));
+        }
+        let population = record.population.map(|p| p as u64);
+        if population != city.population {
+            changes.push(format!(
+                "population {} → {}",
+                city.population
+                    .map_or("unknown".to_string(), |p| p.to_string()),
+                population.map_or("unknown".to_string(), |p| p.to_string())
+            ));
+        }
+        if !changes.is_empty() {
+            println!(
+                "{}",
+                format!(
+                    "  Visited city {} ({}) changed: {}",
+                    city.name,
+                    city.country,
+                    changes.join(", ")
+                )
+                .yellow()
+            );
+        }
+    }
+
+    for id in delta.deleted.keys() {
+        if let Some(city) = visited.get(id) {
+            println!(
+                "{}",
+                format!(
+                    "  Visited city {} ({}) was deleted from GeoNames",
+                    city.name, city.country
+                )
+                .red()
+            );
+        }
+    }
+}
@@ -0,0 +1,502 @@
+use super::cities::visited_cities;
+use super::countries::{get_country_info, load_country_info};
+use crate::config::Config;
+use crate::geonames::{self, Continent, CountryInfo};
+use crate::models::cities::{Cities, Visit};
+use crate::models::countries::Countries;
+use crate::models::summits::Summits;
+use crate::output::{OutputFormat, Table};
+use anyhow::Result;
+use chrono::NaiveDate;
+use clap::ValueEnum;
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    Country,
+    City,
+    Summit,
+}
+
+impl Kind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Kind::Country => "country",
+            Kind::City => "city",
+            Kind::Summit => "summit",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SortKey {
+    Name,
+    /// First visit, undated places last
+    Date,
+    /// North to south
+    Latitude,
+    /// Most inhabitants first
+    Population,
+    /// Highest first
+    Elevation,
+}
+
+/// Which of the recorded places to show, in which order and how.
+pub struct Query {
+    /// Name, alias or ISO code of the only country to show places in.
+    pub country: Option<String>,
+    pub continent: Option<Continent>,
+    /// Only places visited on or after this day.
+    pub since: Option<NaiveDate>,
+    /// Only places visited on or before this day.
+    pub until: Option<NaiveDate>,
+    pub sort: SortKey,
+    pub reverse: bool,
+    pub format: OutputFormat,
+}
+
+/// A recorded country, city or summit as listed.
+#[derive(Debug, Serialize)]
+struct Entry {
+    kind: Kind,
+    name: String,
+    country_iso: String,
+    country: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    continent: Option<Continent>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lat: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lon: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    population: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    elevation: Option<i32>,
+    /// Cities recorded in a country.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cities: Option<usize>,
+    /// For a country, its own visits and those of its cities.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    visits: Vec<Visit>,
+}
+
+/// Everything recorded in the docs, with the GeoNames country details.
+struct Recorded {
+    infos: HashMap<String, CountryInfo>,
+    countries: Countries,
+    cities: BTreeMap<String, Cities>,
+    summits: Summits,
+}
+
+/// Prints the recorded places of one kind.
+pub(crate) async fn list(config: &Config, kind: Kind, query: &Query) -> Result<()> {
+    let recorded = load(config).await?;
+    let entries = match kind {
+        Kind::Country => country_entries(&recorded),
+        Kind::City => city_entries(&recorded),
+        Kind::Summit => summit_entries(&recorded),
+    };
+    print_entries(config, entries, Some(kind), query).await
+}
+
+/// Prints the recorded countries, cities and summits whose name contains
+/// `text`, ignoring case and accents.
+pub(crate) async fn search(config: &Config, text: &str, query: &Query) -> Result<()> {
+    let recorded = load(config).await?;
+    let wanted = geonames::normalize_name(text);
+    let entries = country_entries(&recorded)
+        .into_iter()
+        .chain(city_entries(&recorded))
+        .chain(summit_entries(&recorded))
+        .filter(|entry| geonames::normalize_name(&entry.name).contains(&wanted))
+        .collect();
+    print_entries(config, entries, None, query).await
+}
+
+async fn print_entries(
+    config: &Config,
+    entries: Vec<Entry>,
+    kind: Option<Kind>,
+    query: &Query,
+) -> Result<()> {
+    let country_iso = match &query.country {
+        Some(country) => Some(get_country_info(config, country).await?.0),
+        None => None,
+    };
+    let entries = select(entries, query, country_iso.as_deref());
+
+    match query.format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&entries)?),
+        OutputFormat::Table => print!("{}", table(&entries, kind).to_columns()),
+        OutputFormat::Markdown => print!("{}", table(&entries, kind).to_markdown()),
+        OutputFormat::Csv => print!("{}", table(&entries, kind).to_csv()),
+    }
+    Ok(())
+}
+
+async fn load(config: &Config) -> Result<Recorded> {
+    let infos = load_country_info(config)
+        .await?
+        .into_iter()
+        .map(|info| (info.iso.clone(), info))
+        .collect();
+    let countries_file = config.docs.dir.join(&config.docs.countries_file);
+    let summits_file = config.docs.dir.join(&config.docs.summits_file);
+    Ok(Recorded {
+        infos,
+        countries: Countries::load_from_file(countries_file.to_str().unwrap())?,
+        cities: visited_cities(config)?,
+        summits: if summits_file.exists() {
+            Summits::load_from_file(summits_file.to_str().unwrap())?
+        } else {
+            Summits::new()
+        },
+    })
+}
+
+impl Recorded {
+    /// Display name and continent of a country by ISO code.
+    fn country(&self, iso: &str) -> (String, Option<Continent>) {
+        let info = self.infos.get(iso);
+        let name = self
+            .countries
+            .iter()
+            .find(|(code, _)| *code == iso)
+            .map(|(_, country)| country.name.clone())
+            .or_else(|| info.map(|info| info.country.clone()))
+            .unwrap_or_else(|| iso.to_string());
+        (name, info.map(|info| info.continent))
+    }
+}
+
+fn country_entries(recorded: &Recorded) -> Vec<Entry> {
+    recorded
+        .countries
+        .iter()
+        .map(|(iso, country)| {
+            let info = recorded.infos.get(iso);
+            let cities = recorded.cities.get(iso);
+            let mut visits = country.visits.clone();
+            for city in cities.into_iter().flat_map(|cities| cities.cities.values()) {
+                visits.extend(city.visits.iter().cloned());
+            }
+            visits.sort();
+            visits.dedup();
+            Entry {
+                kind: Kind::Country,
+                name: country.name.clone(),
+                country_iso: iso.to_string(),
+                country: country.name.clone(),
+                continent: info.map(|info| info.continent),
+                lat: None,
+                lon: None,
+                population: info.and_then(|info| info.population),
+                elevation: None,
+                cities: Some(cities.map_or(0, |cities| cities.cities.len())),
+                visits,
+            }
+        })
+        .collect()
+}
+
+fn city_entries(recorded: &Recorded) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    for (iso, cities) in &recorded.cities {
+        let (country, continent) = recorded.country(iso);
+        for (name, city) in &cities.cities {
+            entries.push(Entry {
+                kind: Kind::City,
+                name: name.clone(),
+                country_iso: iso.clone(),
+                country: country.clone(),
+                continent,
+                lat: Some(city.lat),
+                lon: Some(city.lon),
+                population: city.population,
+                elevation: None,
+                cities: None,
+                visits: city.visits.clone(),
+            });
+        }
+    }
+    entries
+}
+
+fn summit_entries(recorded: &Recorded) -> Vec<Entry> {
+    recorded
+        .summits
+        .features
+        .iter()
+        .map(|feature| {
+            let summit = &feature.properties;
+            let iso = summit.country_code.clone().unwrap_or_default();
+            let (country, continent) = recorded.country(&iso);
+            let (lat, lon) = feature.geometry.lat_lon();
+            Entry {
+                kind: Kind::Summit,
+                name: summit.name.clone(),
+                country_iso: iso,
+                country,
+                continent,
+                lat: Some(lat),
+                lon: Some(lon),
+                population: None,
+                elevation: summit.elevation,
+                cities: None,
+                visits: summit
+                    .date
+                    .map(|from| Visit {
+                        from,
+                        to: None,
+                        note: None,
+                    })
+                    .into_iter()
+                    .collect(),
+            }
+        })
+        .collect()
+}
+
+/// Filters and sorts entries as `query` asks, `country_iso` being its
+/// country already resolved.
+fn select(entries: Vec<Entry>, query: &Query, country_iso: Option<&str>) -> Vec<Entry> {
+    let mut entries: Vec<Entry> = entries
+        .into_iter()
+        .filter(|entry| country_iso.is_none_or(|iso| entry.country_iso == iso))
+        .filter(|entry| query.continent.is_none_or(|c| entry.continent == Some(c)))
+        .filter(|entry| {
+            if query.since.is_none() && query.until.is_none() {
+                return true;
+            }
+            entry.visits.iter().any(|visit| {
+                let last = visit.to.unwrap_or(visit.from);
+                query.since.is_none_or(|since| last >= since)
+                    && query.until.is_none_or(|until| visit.from <= until)
+            })
+        })
+        .collect();
+
+    entries.sort_by(|a, b| {
+        let reverse = query.reverse;
+        let order = match query.sort {
+            SortKey::Name if reverse => name_key(b).cmp(&name_key(a)),
+            SortKey::Name => name_key(a).cmp(&name_key(b)),
+            SortKey::Date => known_first(first_visit(a), first_visit(b), reverse),
+            SortKey::Latitude => known_first(a.lat, b.lat, !reverse),
+            SortKey::Population => known_first(a.population, b.population, !reverse),
+            SortKey::Elevation => known_first(a.elevation, b.elevation, !reverse),
+        };
+        order.then_with(|| name_key(a).cmp(&name_key(b)))
+    });
+    entries
+}
+
+/// Orders two optional values, places without one last whichever the
+/// direction.
+fn known_first<T: PartialOrd>(a: Option<T>, b: Option<T>, descending: bool) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => {
+            let order = a.partial_cmp(&b).unwrap_or(Ordering::Equal);
+            if descending { order.reverse() } else { order }
+        }
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+fn name_key(entry: &Entry) -> String {
+    geonames::normalize_name(&entry.name)
+}
+
+fn first_visit(entry: &Entry) -> Option<NaiveDate> {
+    entry.visits.first().map(|visit| visit.from)
+}
+
+/// The columns worth showing for a kind of place; search results mixing all
+/// kinds get the ones they share.
+fn table(entries: &[Entry], kind: Option<Kind>) -> Table {
+    let mut table = match kind {
+        Some(Kind::Country) => Table::new(&[
+            "ISO",
+            "Country",
+            "Continent",
+            "Population",
+            "Cities",
+            "Visited",
+        ])
+        .align_right(&[3, 4]),
+        Some(Kind::City) => Table::new(&["City", "Country", "Lat", "Lon", "Population", "Visited"])
+            .align_right(&[2, 3, 4]),
+        Some(Kind::Summit) => Table::new(&["Summit", "Country", "Elevation", "Lat", "Lon", "Date"])
+            .align_right(&[2, 3, 4]),
+        None => {
+            Table::new(&["Kind", "Name", "Country", "Lat", "Lon", "Visited"]).align_right(&[3, 4])
+        }
+    };
+
+    let text = |value: Option<String>| value.unwrap_or_default();
+    for entry in entries {
+        let visited = visited(&entry.visits);
+        let lat = text(entry.lat.map(|lat| lat.to_string()));
+        let lon = text(entry.lon.map(|lon| lon.to_string()));
+        table.push(match kind {
+            Some(Kind::Country) => vec![
+                entry.country_iso.clone(),
+                entry.name.clone(),
+                text(entry.continent.map(|c| c.name().to_string())),
+                text(entry.population.map(|p| p.to_string())),
+                text(entry.cities.map(|c| c.to_string())),
+                visited,
+            ],
+            Some(Kind::City) => vec![
+                entry.name.clone(),
+                entry.country.clone(),
+                lat,
+                lon,
+                text(entry.population.map(|p| p.to_string())),
+                visited,
+            ],
+            Some(Kind::Summit) => vec![
+                entry.name.clone(),
+                entry.country.clone(),
+                text(entry.elevation.map(|e| e.to_string())),
+                lat,
+                lon,
+                visited,
+            ],
+            None => vec![
+                entry.kind.as_str().to_string(),
+                entry.name.clone(),
+                entry.country.clone(),
+                lat,
+                lon,
+                visited,
+            ],
+        });
+    }
+    table
+}
+
+/// First and last day of the visits, or the day of a single one.
+fn visited(visits: &[Visit]) -> String {
+    let Some(first) = visits.first() else {
+        return String::new();
+    };
+    let last = visits
+        .iter()
+        .map(|visit| visit.to.unwrap_or(visit.from))
+        .max()
+        .unwrap_or(first.from);
+    if last == first.from {
+        first.from.to_string()
+    } else {
+        format!("{} – {last}", first.from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 7, day).unwrap()
+    }
+
+    fn city(name: &str, iso: &str, lat: f64, population: Option<u64>, days: &[u32]) -> Entry {
+        Entry {
+            kind: Kind::City,
+            name: name.to_string(),
+            country_iso: iso.to_string(),
+            country: iso.to_string(),
+            continent: Some(Continent::Europe),
+            lat: Some(lat),
+            lon: Some(0.0),
+            population,
+            elevation: None,
+            cities: None,
+            visits: days
+                .iter()
+                .map(|d| Visit {
+                    from: day(*d),
+                    to: None,
+                    note: None,
+                })
+                .collect(),
+        }
+    }
+
+    fn entries() -> Vec<Entry> {
+        vec![
+            city("Zürich", "CH", 47.37, Some(341730), &[3]),
+            city("Bern", "CH", 46.95, Some(121631), &[]),
+            city("Ålesund", "NO", 62.47, None, &[10, 20]),
+            city("Lyon", "FR", 45.76, Some(522228), &[1]),
+        ]
+    }
+
+    fn query(sort: SortKey) -> Query {
+        Query {
+            country: None,
+            continent: None,
+            since: None,
+            until: None,
+            sort,
+            reverse: false,
+            format: OutputFormat::Table,
+        }
+    }
+
+    fn names(entries: &[Entry]) -> Vec<&str> {
+        entries.iter().map(|entry| entry.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_select_entries() {
+        let by = |query: &Query| names(&select(entries(), query, None)).join(",");
+
+        assert_eq!(by(&query(SortKey::Name)), "Ålesund,Bern,Lyon,Zürich");
+        assert_eq!(by(&query(SortKey::Date)), "Lyon,Zürich,Ålesund,Bern");
+        assert_eq!(by(&query(SortKey::Latitude)), "Ålesund,Zürich,Bern,Lyon");
+        assert_eq!(by(&query(SortKey::Population)), "Lyon,Zürich,Bern,Ålesund");
+        let reversed = Query {
+            reverse: true,
+            ..query(SortKey::Population)
+        };
+        // places without a population stay last
+        assert_eq!(by(&reversed), "Bern,Zürich,Lyon,Ålesund");
+
+        let swiss = select(entries(), &query(SortKey::Name), Some("CH"));
+        assert_eq!(names(&swiss), ["Bern", "Zürich"]);
+
+        let july = Query {
+            since: Some(day(2)),
+            until: Some(day(15)),
+            ..query(SortKey::Date)
+        };
+        assert_eq!(by(&july), "Zürich,Ålesund");
+        let asia = Query {
+            continent: Some(Continent::Asia),
+            ..query(SortKey::Name)
+        };
+        assert!(select(entries(), &asia, None).is_empty());
+    }
+
+    #[test]
+    fn test_visited_and_table() {
+        let entries = select(entries(), &query(SortKey::Date), None);
+        assert_eq!(visited(&entries[2].visits), "2024-07-10 – 2024-07-20");
+        assert_eq!(visited(&entries[0].visits), "2024-07-01");
+        assert_eq!(visited(&entries[3].visits), "");
+
+        let csv = table(&entries, Some(Kind::City)).to_csv();
+        assert!(csv.starts_with(
+            "City,Country,Lat,Lon,Population,Visited\nLyon,FR,45.76,0,522228,2024-07-01\n"
+        ));
+        let csv = table(&entries, None).to_csv();
+        assert!(csv.starts_with("Kind,Name,Country,Lat,Lon,Visited\ncity,Lyon,"));
+    }
+}
@@ -0,0 +1,198 @@
+use super::cities::{cities_file_path, visited_cities};
+use super::countries::{country_key, country_maps, load_country_info, outline_for};
+use crate::config::Config;
+use crate::geonames::CountryInfo;
+use crate::models::cities::Cities;
+use crate::models::countries::{Countries, Country};
+use anyhow::Result;
+use colored::Colorize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+
+/// Moves docs written before countries were keyed by ISO code over to ISO
+/// codes: `countries.json` lists names like `czech` and `czechia`, and the
+/// city files are named after them. Names of the same country are merged into
+/// one record and one `cities/<ISO>.json`. `aliases` maps names that do not
+/// resolve on their own to ISO codes.
+pub(crate) async fn migrate(
+    config: &Config,
+    aliases: &[(String, String)],
+    dry_run: bool,
+) -> Result<()> {
+    let countries_file = config.docs.dir.join(&config.docs.countries_file);
+    let countries_path = countries_file.to_str().unwrap();
+    let (mut countries, mut names) = match Countries::load_legacy(countries_path)? {
+        Some(names) => (Countries::new(), names),
+        None => (
+            Countries::load_from_file(countries_path)?,
+            Default::default(),
+        ),
+    };
+
+    let mut legacy_files = BTreeMap::new();
+    for (stem, cities) in visited_cities(config)? {
+        if !is_iso_code(&stem) {
+            names.insert(stem.clone());
+            legacy_files.insert(stem, cities);
+        }
+    }
+    if names.is_empty() {
+        println!("{}", "Countries are already keyed by ISO code".green());
+        return Ok(());
+    }
+
+    let infos = load_country_info(config).await?;
+    let maps = country_maps(config, &infos);
+    let aliases: HashMap<String, String> = aliases
+        .iter()
+        .map(|(name, iso)| (name.to_lowercase(), iso.to_uppercase()))
+        .collect();
+
+    // resolve and check everything before any file is touched
+    let mut by_iso: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let mut unresolved = Vec::new();
+    for name in names {
+        let iso = aliases
+            .get(&name)
+            .cloned()
+            .or_else(|| maps.resolve_country(&name).map(|(iso, _)| iso))
+            .or_else(|| resolve_loosely(&infos, &name));
+        match iso {
+            Some(iso) => by_iso.entry(iso).or_default().push(name),
+            None => unresolved.push(name),
+        }
+    }
+    if !unresolved.is_empty() {
+        anyhow::bail!(
+            "No country found for {}, map them with --alias <name>=<ISO>",
+            unresolved.join(", ")
+        );
+    }
+    let display_names: HashMap<&str, &str> = infos
+        .iter()
+        .map(|info| (info.iso.as_str(), info.country.as_str()))
+        .collect();
+    let unknown: Vec<String> = by_iso
+        .iter()
+        .filter(|(iso, _)| !display_names.contains_key(iso.as_str()))
+        .map(|(iso, names)| format!("{iso} ({})", names.join(", ")))
+        .collect();
+    if !unknown.is_empty() {
+        anyhow::bail!("Unknown ISO codes: {}", unknown.join(", "));
+    }
+
+    let mut merged = Vec::new();
+    for (iso, names) in by_iso {
+        let display_name = display_names[iso.as_str()].to_string();
+        let iso_file = cities_file_path(config, &iso);
+        let mut cities = if iso_file.exists() {
+            Cities::load_from_file(iso_file.to_str().unwrap())?
+        } else {
+            Cities::new()
+        };
+        for name in &names {
+            if let Some(legacy) = legacy_files.remove(name) {
+                cities.merge(legacy);
+            }
+        }
+
+        println!(
+            "{} → {iso} ({display_name}), {} cities",
+            names.join(", "),
+            cities.cities.len()
+        );
+        merged.push((iso, display_name, names, cities));
+    }
+
+    if dry_run {
+        println!("{}", "Dry run, nothing changed".yellow());
+        return Ok(());
+    }
+
+    // write everything new first, so an error leaves the legacy files intact
+    let mut legacy_paths = Vec::new();
+    for (iso, display_name, names, cities) in &merged {
+        let display_key = country_key(display_name);
+        let candidates =
+            std::iter::once(display_key.as_str()).chain(names.iter().map(String::as_str));
+        countries.add(
+            iso,
+            Country {
+                name: display_name.clone(),
+                outline: outline_for(config, candidates),
+                visits: Vec::new(),
+            },
+        );
+
+        let iso_file = cities_file_path(config, iso);
+        if !cities.is_empty() {
+            cities.save_to_file(iso_file.to_str().unwrap())?;
+        }
+        for name in names {
+            let legacy_file = cities_file_path(config, name);
+            // on a case-insensitive file system `cz.json` is the new file
+            if legacy_file.exists() && !name.eq_ignore_ascii_case(iso) {
+                legacy_paths.push(legacy_file);
+            }
+        }
+    }
+    countries.save_to_file(countries_path)?;
+
+    for legacy_file in legacy_paths {
+        fs::remove_file(&legacy_file)?;
+    }
+    println!("{}", "Countries are now keyed by ISO code".green());
+    Ok(())
+}
+
+fn is_iso_code(stem: &str) -> bool {
+    stem.len() == 2 && stem.bytes().all(|b| b.is_ascii_uppercase())
+}
+
+/// Matches names written by hand: `netherlands` for "The Netherlands", or a
+/// prefix only one country starts with, like `czech` for "Czechia".
+fn resolve_loosely(infos: &[CountryInfo], name: &str) -> Option<String> {
+    let name = name.strip_prefix("the_").unwrap_or(name);
+    let keys: Vec<(String, &str)> = infos
+        .iter()
+        .map(|info| {
+            let key = country_key(&info.country);
+            let key = key.strip_prefix("the_").map(str::to_string).unwrap_or(key);
+            (key, info.iso.as_str())
+        })
+        .collect();
+
+    if let Some((_, iso)) = keys.iter().find(|(key, _)| key == name) {
+        return Some(iso.to_string());
+    }
+    let mut prefixed = keys.iter().filter(|(key, _)| key.starts_with(name));
+    match (prefixed.next(), prefixed.next()) {
+        (Some((_, iso)), None) => Some(iso.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geonames;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_resolve_loosely() -> Result<()> {
+        let path =
+            PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data/countryInfo_sample.txt");
+        let infos: Vec<CountryInfo> = geonames::read_tsv(&path)?;
+
+        let iso = |name| resolve_loosely(&infos, name);
+        assert_eq!(iso("netherlands").as_deref(), Some("NL"));
+        assert_eq!(iso("the_netherlands").as_deref(), Some("NL"));
+        assert_eq!(iso("czech").as_deref(), Some("CZ"));
+        assert_eq!(iso("andorra").as_deref(), Some("AD"));
+        assert_eq!(iso("united_states").as_deref(), Some("US"));
+        assert_eq!(iso("atlantis"), None);
+        // a prefix shared by several countries is ambiguous
+        assert_eq!(iso("united"), None);
+        Ok(())
+    }
+}
@@ -1 +1,10 @@
 pub(crate) mod cities;
+pub(crate) mod countries;
+pub(crate) mod data;
+pub(crate) mod list;
+pub(crate) mod migrate;
+pub(crate) mod places;
+pub(crate) mod stats;
+pub(crate) mod strava;
+pub(crate) mod summits;
+pub(crate) mod tracks;
@@ -0,0 +1,265 @@
+use crate::config::Config;
+use crate::file_ops;
+use crate::geonames::{self, Geoname};
+use crate::index::{self, PlaceKind, SearchIndex};
+use crate::models::cities::Coordinates;
+use crate::prompt;
+use anyhow::Result;
+use colored::Colorize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tokio::task;
+
+const SUGGESTIONS: usize = 5;
+
+/// How to pick between several GeoNames places sharing the requested name.
+pub(crate) struct Selection {
+    pub admin1: Option<String>,
+    pub near: Option<Coordinates>,
+    pub interactive: bool,
+}
+
+impl Selection {
+    /// Whether `candidate` lies in the `admin1` region asked for, if any.
+    pub(crate) fn admits(&self, candidate: &Geoname) -> bool {
+        self.admin1.as_ref().is_none_or(|admin1| {
+            candidate
+                .admin1_code
+                .as_deref()
+                .is_some_and(|code| code.eq_ignore_ascii_case(admin1))
+        })
+    }
+}
+
+/// What [`select_place`] made of the candidates for a name.
+pub(crate) enum Picked {
+    Place(Box<Geoname>),
+    /// No candidate was left to pick from.
+    NotFound,
+    /// The user passed on the interactive choice.
+    Skipped,
+}
+
+pub(crate) fn select_place(
+    name: &str,
+    mut candidates: Vec<Geoname>,
+    selection: &Selection,
+) -> Result<Picked> {
+    candidates.retain(|c| selection.admits(c));
+
+    let near = selection.near.as_ref().map(|c| (c.lat, c.lon));
+    geonames::rank_candidates(&mut candidates, near);
+
+    match candidates.len() {
+        0 => Ok(Picked::NotFound),
+        1 => Ok(Picked::Place(Box::new(candidates.swap_remove(0)))),
+        n if selection.interactive => {
+            let options: Vec<String> = candidates.iter().map(describe_place).collect();
+            let choice = prompt::choose(&format!("'{name}' matches {n} places:"), &options)?;
+            Ok(choice.map_or(Picked::Skipped, |i| {
+                Picked::Place(Box::new(candidates.swap_remove(i)))
+            }))
+        }
+        n => {
+            let reason = if near.is_some() {
+                "closest to --near"
+            } else {
+                "highest rank, population and elevation"
+            };
+            println!(
+                "{}",
+                format!(
+                    "'{name}' matches {n} places, picked {} as {reason}; use --admin1, --near or --interactive to choose another:",
+                    describe_place(&candidates[0])
+                )
+                .yellow()
+            );
+            for other in &candidates[1..] {
+                println!("  {}", describe_place(other));
+            }
+            Ok(Picked::Place(Box::new(candidates.swap_remove(0))))
+        }
+    }
+}
+
+fn describe_place(place: &Geoname) -> String {
+    let mut details = vec![
+        format!("admin1 {}", place.admin1_code.as_deref().unwrap_or("-")),
+        place.feature_code.clone(),
+    ];
+    if let Some(population) = place.population.filter(|p| *p > 0.0) {
+        details.push(format!("population {population}"));
+    }
+    if let Some(elevation) = place.elevation_m() {
+        details.push(format!("{elevation} m"));
+    }
+    details.push(format!("{}, {}", place.latitude, place.longitude));
+
+    format!("{} ({})", place.name, details.join(", "))
+}
+
+/// Downloads and indexes a GeoNames dump if needed, then looks up the
+/// records of one country matching `names`.
+pub(crate) async fn find_geonames(
+    config: &Config,
+    url: &str,
+    country_iso: &str,
+    names: &[String],
+) -> Result<HashMap<String, Vec<Geoname>>> {
+    let data_file = prepare_dump(config, url).await?;
+    let country_iso = country_iso.to_string();
+    let names = names.to_vec();
+    task::spawn_blocking(move || {
+        SearchIndex::open_or_build(&data_file)?.lookup(&country_iso, &names)
+    })
+    .await?
+}
+
+/// Finds the populated place of a GeoNames dump closest to a point, with its
+/// distance in km.
+pub(crate) async fn nearest_geoname(
+    config: &Config,
+    url: &str,
+    lat: f64,
+    lon: f64,
+    max_km: Option<f64>,
+    min_population: u64,
+) -> Result<Option<(Geoname, f64)>> {
+    let data_file = prepare_dump(config, url).await?;
+    task::spawn_blocking(move || {
+        SearchIndex::open_or_build(&data_file)?.nearest(lat, lon, max_km, min_population)
+    })
+    .await?
+}
+
+/// Finds every place of `kind` in a GeoNames dump within `radius_km` of a track,
+/// with its distance to the track in km.
+pub(crate) async fn geonames_near_track(
+    config: &Config,
+    url: &str,
+    track: &[(f64, f64)],
+    radius_km: f64,
+    kind: PlaceKind,
+) -> Result<Vec<(Geoname, f64)>> {
+    let data_file = prepare_dump(config, url).await?;
+    let track = track.to_vec();
+    task::spawn_blocking(move || {
+        SearchIndex::open_or_build(&data_file)?.near_track(&track, radius_km, kind)
+    })
+    .await?
+}
+
+/// Prints the places closest to a name that had no match, for a dump already
+/// fetched by [`find_geonames`].
+pub(crate) async fn print_suggestions<F>(
+    config: &Config,
+    url: &str,
+    country_iso: &str,
+    name: &str,
+    filter: F,
+) -> Result<()>
+where
+    F: Fn(&Geoname) -> bool + Send + 'static,
+{
+    let data_file = dump_source(config, url);
+    let country_iso = country_iso.to_string();
+    let name = name.to_string();
+    let suggestions = task::spawn_blocking(move || {
+        SearchIndex::open_or_build(&data_file)?.suggest(&country_iso, &name, SUGGESTIONS, filter)
+    })
+    .await??;
+
+    if !suggestions.is_empty() {
+        println!("  did you mean:");
+        for suggestion in &suggestions {
+            println!("    {}", describe_place(suggestion));
+        }
+    }
+    Ok(())
+}
+
+/// Makes sure the dump behind `url` is downloaded, returning the file to read
+/// its records from. That is the archive itself, or the copy daily updates
+/// were applied to while it is newer than the archive.
+pub(crate) async fn prepare_dump(config: &Config, url: &str) -> Result<PathBuf> {
+    let archive = dump_path(config, url);
+    if file_ops::ensure_file(url, &archive, &config.download_policy(url)).await? {
+        discard_derived(config, url)?;
+    }
+    Ok(dump_source(config, url))
+}
+
+/// The file to read the records of the dump behind `url` from, see
+/// [`prepare_dump`].
+pub(crate) fn dump_source(config: &Config, url: &str) -> PathBuf {
+    let archive = dump_path(config, url);
+    let patched = patched_dump_path(config, url);
+    let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+    if patched != archive && modified(&patched) >= modified(&archive) {
+        patched
+    } else {
+        archive
+    }
+}
+
+/// URLs of the dumps named after `pattern` already in the download directory.
+/// Per-country dumps are only fetched for countries places were added in, so
+/// this does not download any new ones.
+pub(crate) fn downloaded_dumps(config: &Config, pattern: &str) -> Result<Vec<String>> {
+    let url = |iso: &str| config.country_pattern_url(pattern, iso);
+    let Some((prefix, suffix)) = pattern.split_once("{country}") else {
+        let exists = config.geonames.download_dir.join(pattern).exists();
+        return Ok(exists.then(|| url("")).into_iter().collect());
+    };
+
+    let Ok(entries) = fs::read_dir(&config.geonames.download_dir) else {
+        return Ok(Vec::new());
+    };
+    let mut urls = Vec::new();
+    for entry in entries {
+        let name = entry?.file_name().to_string_lossy().into_owned();
+        if let Some(iso) = name
+            .strip_prefix(prefix)
+            .and_then(|rest| rest.strip_suffix(suffix))
+            && iso.len() == 2
+            && iso.bytes().all(|b| b.is_ascii_uppercase())
+        {
+            urls.push(url(iso));
+        }
+    }
+    urls.sort();
+    Ok(urls)
+}
+
+/// Downloads the dump behind `url` again if the server has a newer one, and
+/// drops what was derived from the old one. Returns whether it changed.
+pub(crate) async fn refresh_dump(config: &Config, url: &str) -> Result<bool> {
+    let updated =
+        file_ops::refresh_file(url, dump_path(config, url), &config.download_policy(url)).await?;
+    if updated {
+        discard_derived(config, url)?;
+    }
+    Ok(updated)
+}
+
+/// Where the dump behind `url` is written to with daily updates applied,
+/// gzip-compressed like the archive it replaces.
+pub(crate) fn patched_dump_path(config: &Config, url: &str) -> PathBuf {
+    dump_path(config, url).with_extension("gz")
+}
+
+fn discard_derived(config: &Config, url: &str) -> Result<()> {
+    let archive = dump_path(config, url);
+    index::remove_index(&archive)?;
+    let patched = patched_dump_path(config, url);
+    if patched != archive && patched.exists() {
+        fs::remove_file(&patched)?;
+    }
+    Ok(())
+}
+
+fn dump_path(config: &Config, url: &str) -> PathBuf {
+    let filename = url.rsplit('/').next().unwrap_or("cities.zip");
+    config.geonames.download_dir.join(filename)
+}
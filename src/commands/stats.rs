@@ -0,0 +1,342 @@
+use super::cities::visited_cities;
+use super::countries::load_country_info;
+use crate::config::Config;
+use crate::geonames::{Continent, CountryInfo};
+use crate::models::countries::Countries;
+use crate::output::{OutputFormat, Table};
+use anyhow::Result;
+use colored::Colorize;
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+
+#[derive(Debug, Serialize, PartialEq)]
+struct Stats {
+    countries: usize,
+    total_countries: usize,
+    cities: usize,
+    area_sq_km: f64,
+    total_area_sq_km: f64,
+    continents: Vec<ContinentStats>,
+    top_countries: Vec<CountryCities>,
+    /// ISO codes of visited countries missing from countryInfo.txt, left out
+    /// of the totals.
+    unknown_countries: Vec<String>,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+struct ContinentStats {
+    continent: Continent,
+    name: &'static str,
+    countries: usize,
+    total_countries: usize,
+    area_sq_km: f64,
+    total_area_sq_km: f64,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+struct CountryCities {
+    iso: String,
+    name: String,
+    cities: usize,
+}
+
+/// Prints how much of the world and of each continent was visited.
+pub(crate) async fn stats(config: &Config, format: OutputFormat, top: usize) -> Result<()> {
+    let infos = load_country_info(config).await?;
+    let countries_file = config.docs.dir.join(&config.docs.countries_file);
+    let visited = Countries::load_from_file(countries_file.to_str().unwrap())?;
+    let city_counts = visited_cities(config)?
+        .into_iter()
+        .map(|(country, cities)| (country, cities.cities.len()))
+        .collect();
+
+    let stats = compute(
+        &infos,
+        visited.iter().map(|(iso, _)| iso),
+        &city_counts,
+        top,
+    );
+    match format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&stats)?),
+        OutputFormat::Table | OutputFormat::Markdown => {
+            print!("{}", render(&stats, format == OutputFormat::Markdown))
+        }
+        OutputFormat::Csv => print!("{}", csv_table(&stats).to_csv()),
+    }
+    if format == OutputFormat::Table && !stats.unknown_countries.is_empty() {
+        println!(
+            "{}",
+            format!(
+                "Not in countryInfo.txt, left out: {}",
+                stats.unknown_countries.join(", ")
+            )
+            .yellow()
+        );
+    }
+    Ok(())
+}
+
+fn compute<'a, I>(
+    infos: &[CountryInfo],
+    visited: I,
+    city_counts: &BTreeMap<String, usize>,
+    top: usize,
+) -> Stats
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let by_iso: HashMap<&str, &CountryInfo> =
+        infos.iter().map(|info| (info.iso.as_str(), info)).collect();
+
+    let mut continents: BTreeMap<Continent, ContinentStats> = BTreeMap::new();
+    for info in infos {
+        let stats = continents
+            .entry(info.continent)
+            .or_insert_with(|| ContinentStats {
+                continent: info.continent,
+                name: info.continent.name(),
+                countries: 0,
+                total_countries: 0,
+                area_sq_km: 0.0,
+                total_area_sq_km: 0.0,
+            });
+        stats.total_countries += 1;
+        stats.total_area_sq_km += info.area_sq_km.unwrap_or(0.0);
+    }
+
+    let mut countries = 0;
+    let mut unknown_countries = Vec::new();
+    for iso in visited {
+        let Some(info) = by_iso.get(iso) else {
+            unknown_countries.push(iso.to_string());
+            continue;
+        };
+        countries += 1;
+        let stats = continents.get_mut(&info.continent).unwrap();
+        stats.countries += 1;
+        stats.area_sq_km += info.area_sq_km.unwrap_or(0.0);
+    }
+
+    // cities of unknown countries are left out like the countries themselves
+    let mut cities = 0;
+    let mut top_countries = Vec::new();
+    for (iso, &count) in city_counts {
+        let Some(info) = by_iso.get(iso.as_str()) else {
+            if !unknown_countries.contains(iso) {
+                unknown_countries.push(iso.clone());
+            }
+            continue;
+        };
+        cities += count;
+        if count > 0 {
+            top_countries.push(CountryCities {
+                iso: iso.clone(),
+                name: info.country.clone(),
+                cities: count,
+            });
+        }
+    }
+    // stable sort keeps ties in alphabetical order
+    top_countries.sort_by_key(|c| Reverse(c.cities));
+    top_countries.truncate(top);
+
+    let continents: Vec<ContinentStats> = continents.into_values().collect();
+    Stats {
+        countries,
+        total_countries: infos.len(),
+        cities,
+        area_sq_km: continents.iter().map(|c| c.area_sq_km).sum(),
+        total_area_sq_km: continents.iter().map(|c| c.total_area_sq_km).sum(),
+        continents,
+        top_countries,
+        unknown_countries,
+    }
+}
+
+fn render(stats: &Stats, markdown: bool) -> String {
+    let continents = continent_table(stats);
+
+    let mut top = Table::new(&["Country", "Cities"]).align_right(&[1]);
+    for country in &stats.top_countries {
+        top.push(vec![country.name.clone(), country.cities.to_string()]);
+    }
+
+    let summary = format!(
+        "Visited {} of {} countries ({}) and {} cities, {:.0} km² of {:.0} km² ({}).",
+        stats.countries,
+        stats.total_countries,
+        percent(stats.countries as f64, stats.total_countries as f64),
+        stats.cities,
+        stats.area_sq_km,
+        stats.total_area_sq_km,
+        percent(stats.area_sq_km, stats.total_area_sq_km)
+    );
+
+    if markdown {
+        format!(
+            "{summary}\n\n## By continent\n\n{}\n## Top countries by cities\n\n{}",
+            continents.to_markdown(),
+            top.to_markdown()
+        )
+    } else {
+        format!(
+            "{summary}\n\n{}\nTop countries by cities:\n{}",
+            continents.to_columns(),
+            top.to_columns()
+        )
+    }
+}
+
+fn continent_table(stats: &Stats) -> Table {
+    let mut continents = Table::new(&["Continent", "Countries", "Share", "Area km²", "Area share"])
+        .align_right(&[1, 2, 3, 4]);
+    for c in &stats.continents {
+        continents.push(vec![
+            c.name.to_string(),
+            format!("{} / {}", c.countries, c.total_countries),
+            percent(c.countries as f64, c.total_countries as f64),
+            format!("{:.0}", c.area_sq_km),
+            percent(c.area_sq_km, c.total_area_sq_km),
+        ]);
+    }
+    continents
+}
+
+/// The summary, the continents and the top countries as one table, told apart
+/// by the leading `section` column. Cells that do not apply to a section are
+/// left empty.
+fn csv_table(stats: &Stats) -> Table {
+    let mut table = Table::new(&[
+        "section",
+        "name",
+        "countries",
+        "total_countries",
+        "area_sq_km",
+        "total_area_sq_km",
+        "cities",
+    ]);
+    table.push(vec![
+        "total".to_string(),
+        "World".to_string(),
+        stats.countries.to_string(),
+        stats.total_countries.to_string(),
+        format!("{:.0}", stats.area_sq_km),
+        format!("{:.0}", stats.total_area_sq_km),
+        stats.cities.to_string(),
+    ]);
+    for c in &stats.continents {
+        table.push(vec![
+            "continent".to_string(),
+            c.name.to_string(),
+            c.countries.to_string(),
+            c.total_countries.to_string(),
+            format!("{:.0}", c.area_sq_km),
+            format!("{:.0}", c.total_area_sq_km),
+            String::new(),
+        ]);
+    }
+    for country in &stats.top_countries {
+        table.push(vec![
+            "top_country".to_string(),
+            country.name.clone(),
+            String::new(),
+            String::new(),
+            String::new(),
+            String::new(),
+            country.cities.to_string(),
+        ]);
+    }
+    table
+}
+
+fn percent(part: f64, total: f64) -> String {
+    if total > 0.0 {
+        format!("{:.1}%", part / total * 100.0)
+    } else {
+        "-".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geonames;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_compute_stats() -> Result<()> {
+        let path =
+            PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data/countryInfo_sample.txt");
+        let infos: Vec<CountryInfo> = geonames::read_tsv(&path)?;
+        let city_counts = BTreeMap::from([
+            ("AD".to_string(), 4),
+            ("AE".to_string(), 2),
+            ("XX".to_string(), 1),
+            ("YY".to_string(), 3),
+        ]);
+
+        let stats = compute(&infos, ["AD", "AE", "XX"], &city_counts, 3);
+        assert_eq!(stats.countries, 2);
+        assert_eq!(stats.total_countries, infos.len());
+        assert_eq!(stats.cities, 6);
+        assert_eq!(stats.area_sq_km, 468.0 + 82880.0);
+        assert_eq!(stats.unknown_countries, ["XX", "YY"]);
+        assert_eq!(
+            stats.top_countries,
+            [
+                CountryCities {
+                    iso: "AD".to_string(),
+                    name: "Andorra".to_string(),
+                    cities: 4
+                },
+                CountryCities {
+                    iso: "AE".to_string(),
+                    name: "United Arab Emirates".to_string(),
+                    cities: 2
+                },
+            ]
+        );
+
+        let europe = stats
+            .continents
+            .iter()
+            .find(|c| c.continent == Continent::Europe)
+            .unwrap();
+        assert_eq!(europe.countries, 1);
+        assert_eq!(
+            europe.total_countries,
+            infos
+                .iter()
+                .filter(|i| i.continent == Continent::Europe)
+                .count()
+        );
+        assert_eq!(europe.area_sq_km, 468.0);
+
+        let text = render(&stats, false);
+        assert!(text.starts_with(&format!(
+            "Visited 2 of {} countries ({})",
+            infos.len(),
+            percent(2.0, infos.len() as f64)
+        )));
+        assert!(render(&stats, true).contains("| Andorra | 4 |"));
+
+        let csv = csv_table(&stats).to_csv();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(
+            lines[0],
+            "section,name,countries,total_countries,area_sq_km,total_area_sq_km,cities"
+        );
+        assert!(lines[1].starts_with(&format!("total,World,2,{},83348,", infos.len())));
+        assert!(lines[1].ends_with(",6"));
+        assert!(lines.iter().any(|l| l.starts_with("continent,Europe,1,")));
+        assert_eq!(
+            &lines[lines.len() - 2..],
+            [
+                "top_country,Andorra,,,,,4",
+                "top_country,United Arab Emirates,,,,,2"
+            ]
+        );
+        Ok(())
+    }
+}
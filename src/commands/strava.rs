@@ -0,0 +1,103 @@
+use super::tracks::{self, TrackOptions, TrackOutcome};
+use crate::config::Config;
+use crate::strava::{self, Client, Session};
+use anyhow::Result;
+use chrono::{NaiveDate, TimeZone, Utc};
+use colored::Colorize;
+
+pub(crate) async fn login(config: &Config) -> Result<()> {
+    let strava_config = config.strava()?;
+    let client = Client::new(strava_config)?;
+
+    let state = strava::new_state()?;
+    println!("Open this page to allow access to your Strava activities:");
+    println!("  {}", client.authorize_url(&state)?);
+    println!("Waiting for the authorization...");
+
+    let code = client.receive_code(&state).await?;
+    let mut session = client.exchange_code(&code).await?;
+
+    // keep the sync progress of an earlier login
+    if let Ok(previous) = Session::load(&strava_config.session_file) {
+        session.synced_until = previous.synced_until;
+    }
+    session.save(&strava_config.session_file)?;
+    println!(
+        "{}",
+        format!(
+            "Logged in to Strava, session stored in {}",
+            strava_config.session_file.display()
+        )
+        .green()
+    );
+    Ok(())
+}
+
+/// Records the cities and summits along the Strava activities started after
+/// `after`, or after the last synced one.
+pub(crate) async fn sync(
+    config: &Config,
+    after: Option<NaiveDate>,
+    options: &TrackOptions,
+) -> Result<()> {
+    let strava_config = config.strava()?;
+    let client = Client::new(strava_config)?;
+
+    let mut session = Session::load(&strava_config.session_file)?;
+    if client.refresh(&mut session).await? {
+        session.save(&strava_config.session_file)?;
+    }
+
+    let after = match after {
+        Some(day) => day
+            .and_hms_opt(0, 0, 0)
+            .map(|start| Utc.from_utc_datetime(&start)),
+        None => session.synced_until,
+    };
+    let activities = client.activities(&session, after).await?;
+    if activities.is_empty() {
+        println!("{}", "No new Strava activities".yellow());
+        return Ok(());
+    }
+    println!("Found {} Strava activities", activities.len());
+
+    for activity in &activities {
+        println!();
+        println!(
+            "{}",
+            format!(
+                "{} {} ({})",
+                activity.start_date.date_naive(),
+                activity.name,
+                activity.sport_type.as_deref().unwrap_or("activity")
+            )
+            .bold()
+        );
+
+        let outcome = match activity.track(options.point_spacing_km())? {
+            Some(track) => Some(tracks::record_track(config, &track, options).await?),
+            None => {
+                println!("{}", "No GPS route, skipping".yellow());
+                None
+            }
+        };
+        match outcome {
+            // a declined activity is offered again by the next sync
+            Some(TrackOutcome::Declined) => {
+                println!(
+                    "{}",
+                    "Stopping, the next sync starts at this activity".yellow()
+                );
+                break;
+            }
+            _ if options.dry_run => {}
+            // remember progress as we go, so an interrupted sync picks up here
+            _ => {
+                session.synced_until = Some(activity.start_date);
+                session.save(&strava_config.session_file)?;
+            }
+        }
+    }
+
+    Ok(())
+}
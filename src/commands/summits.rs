@@ -0,0 +1,115 @@
+use super::countries::get_country_info;
+use super::places::{self, Picked, Selection};
+use crate::config::Config;
+use crate::geonames::Geoname;
+use crate::models::summits::{Point, Summit, Summits};
+use anyhow::Result;
+use chrono::NaiveDate;
+use colored::Colorize;
+
+pub(crate) async fn add_summits(
+    config: &Config,
+    country: &str,
+    names: &[String],
+    selection: &Selection,
+    date: Option<NaiveDate>,
+) -> Result<()> {
+    let (country_iso, country_name) = get_country_info(config, country).await?;
+
+    let mut picked = Vec::new();
+
+    let found = places::find_geonames(
+        config,
+        &config.summits_url(&country_iso),
+        &country_iso,
+        names,
+    )
+    .await?;
+
+    for (name, mut candidates) in found {
+        candidates.retain(|c| c.is_summit());
+        let no_candidates = candidates.is_empty();
+        let peak = match places::select_place(&name, candidates, selection)? {
+            Picked::Place(peak) => *peak,
+            Picked::NotFound => {
+                println!(
+                    "{}",
+                    format!("Summit '{name}' not found in country '{country_name}'").red()
+                );
+                if no_candidates {
+                    places::print_suggestions(
+                        config,
+                        &config.summits_url(&country_iso),
+                        &country_iso,
+                        &name,
+                        |p| p.is_summit(),
+                    )
+                    .await?;
+                }
+                continue;
+            }
+            Picked::Skipped => {
+                println!("{}", format!("Skipped summit '{name}'").yellow());
+                continue;
+            }
+        };
+        picked.push(peak);
+    }
+
+    record_summits(config, &picked, date)
+}
+
+/// Appends already resolved GeoNames peaks to the summits file.
+pub(crate) fn record_summits(
+    config: &Config,
+    picked: &[Geoname],
+    date: Option<NaiveDate>,
+) -> Result<()> {
+    let summits_file = config.docs.dir.join(&config.docs.summits_file);
+    // unlike city files summits.json is edited by hand too, so never overwrite
+    // a file that fails to parse
+    let mut summits = if summits_file.exists() {
+        Summits::load_from_file(summits_file.to_str().unwrap())?
+    } else {
+        Summits::new()
+    };
+
+    let mut is_changed = false;
+
+    for peak in picked {
+        let summit = Summit {
+            name: peak.name.clone(),
+            elevation: peak.elevation_m(),
+            date,
+            geonameid: Some(peak.geonameid),
+            country_code: peak.country_code.clone(),
+            extra: Default::default(),
+        };
+        let elevation = summit
+            .elevation
+            .map_or_else(|| "unknown elevation".to_string(), |e| format!("{e} m"));
+
+        if summits.add(summit, Point::new(peak.latitude, peak.longitude)) {
+            is_changed = true;
+            println!(
+                "{}",
+                format!(
+                    "Added summit: {} ({elevation}, {}, {})",
+                    peak.name, peak.latitude, peak.longitude
+                )
+                .green()
+            );
+        } else {
+            println!(
+                "{}",
+                format!("Summit '{}' already exists", peak.name).yellow()
+            );
+        }
+    }
+
+    if is_changed {
+        summits.save_to_file(summits_file.to_str().unwrap())?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,234 @@
+use super::cities::record_cities;
+use super::countries::update_country;
+use super::places;
+use super::summits::record_summits;
+use crate::config::Config;
+use crate::geonames::Geoname;
+use crate::gpx::{self, Track};
+use crate::index::PlaceKind;
+use crate::models::cities::Visit;
+use crate::prompt;
+use anyhow::Result;
+use colored::Colorize;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::path::Path;
+use tokio::task;
+
+pub(crate) struct TrackOptions {
+    /// Populated places closer than this to the track count as visited.
+    pub city_radius_km: f64,
+    /// Peaks closer than this to the track count as reached.
+    pub summit_radius_km: f64,
+    pub min_population: u64,
+    /// Only list what would be recorded.
+    pub dry_run: bool,
+    /// Record without asking for confirmation.
+    pub yes: bool,
+}
+
+/// What became of the places along a track.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TrackOutcome {
+    Recorded,
+    NothingFound,
+    DryRun,
+    /// The user did not confirm recording the places.
+    Declined,
+}
+
+impl TrackOptions {
+    /// Spacing of track points that is fine enough to not step over a summit
+    /// between two of them.
+    pub fn point_spacing_km(&self) -> f64 {
+        self.summit_radius_km.min(self.city_radius_km) / 2.0
+    }
+}
+
+pub(crate) async fn import_gpx(config: &Config, file: &Path, import: &TrackOptions) -> Result<()> {
+    let spacing_km = import.point_spacing_km();
+    let path = file.to_path_buf();
+    let track = task::spawn_blocking(move || gpx::read_track(&path, spacing_km)).await??;
+    println!(
+        "Read {} track points from {}",
+        track.points.len(),
+        file.display()
+    );
+    record_track(config, &track, import).await?;
+    Ok(())
+}
+
+/// Lists the cities and summits along a track and, once confirmed, records
+/// them with the days the track was recorded on.
+pub(crate) async fn record_track(
+    config: &Config,
+    track: &Track,
+    import: &TrackOptions,
+) -> Result<TrackOutcome> {
+    let global_cities = places::geonames_near_track(
+        config,
+        &config.cities_url(),
+        &track.points,
+        import.city_radius_km,
+        PlaceKind::Populated,
+    )
+    .await?;
+    let countries = track_countries(config, &track.points, &global_cities).await?;
+
+    // per-country dumps hold the villages the global file lacks
+    let country_urls: Vec<String> = countries
+        .iter()
+        .flat_map(|code| config.country_city_urls(code))
+        .collect();
+    let cities = if country_urls.is_empty() {
+        global_cities
+    } else {
+        near_track_in_dumps(
+            config,
+            country_urls,
+            &track.points,
+            import.city_radius_km,
+            PlaceKind::Populated,
+        )
+        .await?
+    };
+    let cities: Vec<(Geoname, f64)> = cities
+        .into_iter()
+        .filter(|(city, _)| city.population.unwrap_or(0.0) as u64 >= import.min_population)
+        .collect();
+
+    let urls = countries.iter().map(|code| config.summits_url(code));
+    let summits = near_track_in_dumps(
+        config,
+        urls,
+        &track.points,
+        import.summit_radius_km,
+        PlaceKind::Summit,
+    )
+    .await?;
+
+    let visit = track.start.map(|start| {
+        let from = start.date_naive();
+        Visit {
+            from,
+            to: track
+                .end
+                .map(|end| end.date_naive())
+                .filter(|to| *to != from),
+            note: None,
+        }
+    });
+
+    let mut by_country: BTreeMap<String, Vec<(Geoname, f64)>> = BTreeMap::new();
+    for (city, distance) in cities {
+        if let Some(code) = city.country_code.clone() {
+            by_country.entry(code).or_default().push((city, distance));
+        }
+    }
+
+    print_listing(&by_country, &summits, visit.as_ref(), import);
+
+    if by_country.is_empty() && summits.is_empty() {
+        println!("{}", "Nothing to record".yellow());
+        return Ok(TrackOutcome::NothingFound);
+    }
+    if import.dry_run {
+        println!("{}", "Dry run, nothing recorded".yellow());
+        return Ok(TrackOutcome::DryRun);
+    }
+    if !import.yes && !prompt::confirm("Record these places?")? {
+        return Ok(TrackOutcome::Declined);
+    }
+
+    for (code, cities) in by_country {
+        let (country_iso, country_name) = update_country(config, &code).await?;
+        let cities: Vec<Geoname> = cities.into_iter().map(|(city, _)| city).collect();
+        record_cities(config, &country_iso, &country_name, &cities, visit.as_ref())?;
+    }
+
+    if !summits.is_empty() {
+        let peaks: Vec<Geoname> = summits.into_iter().map(|(peak, _)| peak).collect();
+        record_summits(config, &peaks, visit.as_ref().map(|v| v.from))?;
+    }
+
+    Ok(TrackOutcome::Recorded)
+}
+
+/// Countries a track passes through, known from the places found along it and
+/// from its end points. Per-country dumps are searched for these.
+async fn track_countries(
+    config: &Config,
+    track: &[(f64, f64)],
+    cities: &[(Geoname, f64)],
+) -> Result<BTreeSet<String>> {
+    let mut countries: BTreeSet<String> = cities
+        .iter()
+        .filter_map(|(city, _)| city.country_code.clone())
+        .collect();
+    for &(lat, lon) in [track.first(), track.last()].into_iter().flatten() {
+        if let Some((place, _)) =
+            places::nearest_geoname(config, &config.cities_url(), lat, lon, None, 0).await?
+            && let Some(code) = place.country_code
+        {
+            countries.insert(code);
+        }
+    }
+    Ok(countries)
+}
+
+/// Places of `kind` near a track in each of the dumps, nearest first. Dumps
+/// may overlap, e.g. when every country maps to `allCountries.zip`.
+async fn near_track_in_dumps<I>(
+    config: &Config,
+    urls: I,
+    track: &[(f64, f64)],
+    radius_km: f64,
+    kind: PlaceKind,
+) -> Result<Vec<(Geoname, f64)>>
+where
+    I: IntoIterator<Item = String>,
+{
+    let urls: BTreeSet<String> = urls.into_iter().collect();
+
+    let mut seen = HashSet::new();
+    let mut places = Vec::new();
+    for url in urls {
+        let found = places::geonames_near_track(config, &url, track, radius_km, kind).await?;
+        for (place, distance) in found {
+            if seen.insert(place.geonameid) {
+                places.push((place, distance));
+            }
+        }
+    }
+    places.sort_by(|a, b| a.1.total_cmp(&b.1));
+    Ok(places)
+}
+
+fn print_listing(
+    by_country: &BTreeMap<String, Vec<(Geoname, f64)>>,
+    summits: &[(Geoname, f64)],
+    visit: Option<&Visit>,
+    import: &TrackOptions,
+) {
+    match visit {
+        Some(visit) => println!("Visit date: {visit}"),
+        None => println!("Track has no timestamps, no visit date will be recorded"),
+    }
+
+    println!("Cities within {} km of the track:", import.city_radius_km);
+    for (code, cities) in by_country {
+        for (city, distance) in cities {
+            println!("  {code}  {} ({distance:.2} km)", city.name);
+        }
+    }
+
+    println!(
+        "Summits within {} km of the track:",
+        import.summit_radius_km
+    );
+    for (peak, distance) in summits {
+        let elevation = peak
+            .elevation_m()
+            .map_or_else(|| "unknown elevation".to_string(), |e| format!("{e} m"));
+        println!("  {} ({elevation}, {distance:.2} km)", peak.name);
+    }
+}
@@ -1,18 +1,45 @@
+use crate::file_ops::DownloadPolicy;
+use anyhow::Context;
+use chrono::NaiveDate;
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::path::PathBuf;
 
 #[derive(Debug, Deserialize)]
 pub struct Config {
     pub geonames: GeoNames,
     pub docs: Docs,
+    pub strava: Option<Strava>,
+    /// Extra names countries can be given by, mapped to ISO codes, e.g.
+    /// `Blighty = "GB"`. They take precedence over GeoNames names.
+    #[serde(default)]
+    pub country_aliases: HashMap<String, String>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct GeoNames {
     pub base_url: String,
     pub country_info_file: String,
+    /// Global dump of populated places, e.g. `cities500.zip`, searched by
+    /// position and kept current by daily updates.
     pub cities_file: String,
+    /// Dumps cities are looked up in by name, in order, `{country}` standing
+    /// for the ISO code. Later ones are only searched for the names earlier
+    /// ones lack. Defaults to `cities_file` alone.
+    #[serde(default)]
+    pub cities_sources: Vec<String>,
+    /// Dump used to look up summits, either `allCountries.zip` or a per-country
+    /// file where `{country}` is replaced by the ISO code, e.g. `{country}.zip`.
+    #[serde(default = "default_summits_file")]
+    pub summits_file: String,
     pub download_dir: PathBuf,
+    /// Ask the server for newer dumps once the local ones were last checked
+    /// this many days ago. Without it, dumps are only refreshed by `update-data`.
+    #[serde(default)]
+    pub max_age_days: Option<u64>,
+    /// Expected SHA-256 checksums of downloaded files, by file name.
+    #[serde(default)]
+    pub sha256: HashMap<String, String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -20,6 +47,43 @@ pub struct Docs {
     pub dir: PathBuf,
     pub countries_file: String,
     pub cities_folder: String,
+    #[serde(default = "default_docs_summits_file")]
+    pub summits_file: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Strava {
+    pub client_id: String,
+    pub client_secret: String,
+    /// Web and API host, overridable to test against a local server.
+    #[serde(default = "default_strava_base_url")]
+    pub base_url: String,
+    /// Local port the authorization redirect is received on.
+    #[serde(default = "default_strava_redirect_port")]
+    pub redirect_port: u16,
+    /// Where the access tokens and the sync progress are kept.
+    #[serde(default = "default_strava_session_file")]
+    pub session_file: PathBuf,
+}
+
+fn default_summits_file() -> String {
+    "{country}.zip".to_string()
+}
+
+fn default_docs_summits_file() -> String {
+    "summits.json".to_string()
+}
+
+fn default_strava_base_url() -> String {
+    "https://www.strava.com".to_string()
+}
+
+fn default_strava_redirect_port() -> u16 {
+    8911
+}
+
+fn default_strava_session_file() -> PathBuf {
+    PathBuf::from("strava_session.json")
 }
 
 impl Config {
@@ -39,4 +103,127 @@ impl Config {
     pub fn cities_url(&self) -> String {
         format!("{}{}", self.geonames.base_url, self.geonames.cities_file)
     }
+
+    pub fn summits_url(&self, country_iso: &str) -> String {
+        self.country_pattern_url(&self.geonames.summits_file, country_iso)
+    }
+
+    /// Dumps the cities of a country are looked up in by name, in order.
+    pub fn city_lookup_urls(&self, country_iso: &str) -> Vec<String> {
+        if self.geonames.cities_sources.is_empty() {
+            return vec![self.cities_url()];
+        }
+        let mut urls: Vec<String> = Vec::new();
+        for pattern in &self.geonames.cities_sources {
+            let url = self.country_pattern_url(pattern, country_iso);
+            if !urls.contains(&url) {
+                urls.push(url);
+            }
+        }
+        urls
+    }
+
+    /// The per-country dumps among the city sources. Unlike global ones they are
+    /// cheap to search by position once the country is known.
+    pub fn country_city_urls(&self, country_iso: &str) -> Vec<String> {
+        self.geonames
+            .cities_sources
+            .iter()
+            .filter(|pattern| pattern.contains("{country}"))
+            .map(|pattern| self.country_pattern_url(pattern, country_iso))
+            .collect()
+    }
+
+    /// URL of a file named after `pattern`, where `{country}` stands for the
+    /// ISO code.
+    pub fn country_pattern_url(&self, pattern: &str, country_iso: &str) -> String {
+        format!(
+            "{}{}",
+            self.geonames.base_url,
+            pattern.replace("{country}", &country_iso.to_uppercase())
+        )
+    }
+
+    /// How the GeoNames file behind `url` is refreshed and verified.
+    pub fn download_policy(&self, url: &str) -> DownloadPolicy<'_> {
+        let filename = url.rsplit('/').next().unwrap_or(url);
+        DownloadPolicy {
+            max_age_days: self.geonames.max_age_days,
+            sha256: self.geonames.sha256.get(filename).map(String::as_str),
+        }
+    }
+
+    /// Records changed in GeoNames on `day`, published the day after.
+    pub fn modifications_url(&self, day: NaiveDate) -> String {
+        format!("{}modifications-{day}.txt", self.geonames.base_url)
+    }
+
+    /// Ids of records deleted from GeoNames on `day`.
+    pub fn deletes_url(&self, day: NaiveDate) -> String {
+        format!("{}deletes-{day}.txt", self.geonames.base_url)
+    }
+
+    pub fn strava(&self) -> anyhow::Result<&Strava> {
+        self.strava
+            .as_ref()
+            .context("Strava is not configured, add a [strava] section to the config file")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_config(download_dir: &str, cities_sources: &[&str]) -> Config {
+        toml::from_str(&format!(
+            r#"
+            [geonames]
+            base_url = "https://example.org/dump/"
+            country_info_file = "countryInfo.txt"
+            cities_file = "cities500.zip"
+            cities_sources = {cities_sources:?}
+            download_dir = "{download_dir}"
+
+            [docs]
+            dir = "docs"
+            countries_file = "countries.json"
+            cities_folder = "cities"
+            "#
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_city_lookup_urls() {
+        let config = test_config("downloads", &[]);
+        assert_eq!(
+            config.city_lookup_urls("ch"),
+            ["https://example.org/dump/cities500.zip"]
+        );
+        assert!(config.country_city_urls("ch").is_empty());
+
+        // kept in order, the country filled in and repeats dropped
+        let config = test_config(
+            "downloads",
+            &[
+                "cities500.zip",
+                "{country}.zip",
+                "CH.zip",
+                "allCountries.zip",
+                "cities500.zip",
+            ],
+        );
+        assert_eq!(
+            config.city_lookup_urls("ch"),
+            [
+                "https://example.org/dump/cities500.zip",
+                "https://example.org/dump/CH.zip",
+                "https://example.org/dump/allCountries.zip",
+            ]
+        );
+        assert_eq!(
+            config.country_city_urls("ch"),
+            ["https://example.org/dump/CH.zip"]
+        );
+    }
 }
@@ -0,0 +1,207 @@
+use crate::geonames::{self, AlternateNames, FeatureClass, Geoname, RecordFilter};
+use anyhow::{Context, Result};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// Administrative seats are part of the `citiesN` dumps whatever their
+/// population.
+const SEAT_FEATURE_CODES: [&str; 5] = ["PPLC", "PPLA", "PPLA2", "PPLA3", "PPLA4"];
+
+/// Changes GeoNames published for one day, from its `modifications-<day>.txt`
+/// and `deletes-<day>.txt` files.
+#[derive(Debug, Default)]
+pub struct Delta {
+    /// Changed or new records with the line they were read from.
+    pub modified: BTreeMap<i64, (Geoname, String)>,
+    /// Ids of deleted records with the name they had.
+    pub deleted: BTreeMap<i64, String>,
+}
+
+/// What applying a delta did to a dump.
+#[derive(Debug, Default, PartialEq)]
+pub struct Applied {
+    pub updated: usize,
+    pub added: usize,
+    pub deleted: usize,
+}
+
+impl Delta {
+    /// Reads a modifications file, which has the columns of the dumps, and a
+    /// deletes file with `geonameid`, name and a comment per line.
+    pub fn read<P, Q>(modifications: P, deletes: Q) -> Result<Self>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        let mut delta = Delta::default();
+
+        let modifications = BufReader::new(File::open(modifications)?);
+        for row in geonames::rows(modifications, &RecordFilter::default()) {
+            let row = row?;
+            let record = row.to_geoname(AlternateNames::Keep)?;
+            delta
+                .modified
+                .insert(record.geonameid, (record, row.line().to_string()));
+        }
+
+        for line in BufReader::new(File::open(deletes)?).lines() {
+            let line = line?;
+            let mut fields = line.split('\t');
+            let Some(id) = fields.next().filter(|id| !id.is_empty()) else {
+                continue;
+            };
+            let id = id
+                .parse()
+                .with_context(|| format!("Invalid deletion '{line}'"))?;
+            delta
+                .deleted
+                .insert(id, fields.next().unwrap_or_default().to_string());
+        }
+
+        Ok(delta)
+    }
+
+    /// Writes the dump in `source` with the delta applied to `target`, which
+    /// may be the same file and is gzip-compressed when named `.gz`. Modified
+    /// records are kept and new ones added only when `belongs` accepts them,
+    /// since the modifications cover all of GeoNames and not just one dump;
+    /// records that no longer belong are dropped and counted as deleted.
+    pub fn apply<F>(&self, source: &Path, target: &Path, belongs: F) -> Result<Applied>
+    where
+        F: Fn(&Geoname) -> bool,
+    {
+        let tmp_file = target.with_extension("tmp");
+        let file = BufWriter::new(File::create(&tmp_file)?);
+        let applied = if target.extension().is_some_and(|ext| ext == "gz") {
+            let mut encoder = GzEncoder::new(file, Compression::default());
+            let applied = self.write_applied(source, &mut encoder, belongs)?;
+            encoder.finish()?.flush()?;
+            applied
+        } else {
+            let mut file = file;
+            let applied = self.write_applied(source, &mut file, belongs)?;
+            file.flush()?;
+            applied
+        };
+        fs::rename(&tmp_file, target)?;
+        Ok(applied)
+    }
+
+    fn write_applied<W, F>(&self, source: &Path, writer: &mut W, belongs: F) -> Result<Applied>
+    where
+        W: Write,
+        F: Fn(&Geoname) -> bool,
+    {
+        let mut applied = Applied::default();
+        let mut seen = HashSet::new();
+
+        for line in geonames::open_dump(source)?.lines() {
+            let line = line?;
+            let id = line
+                .split('\t')
+                .next()
+                .and_then(|id| id.parse::<i64>().ok());
+            match id {
+                Some(id) if self.deleted.contains_key(&id) => {
+                    applied.deleted += 1;
+                    continue;
+                }
+                Some(id) => {
+                    if let Some((record, modified)) = self.modified.get(&id) {
+                        seen.insert(id);
+                        // e.g. its population fell below the dump's threshold
+                        if belongs(record) {
+                            applied.updated += 1;
+                            writeln!(writer, "{modified}")?;
+                        } else {
+                            applied.deleted += 1;
+                        }
+                        continue;
+                    }
+                }
+                None => {}
+            }
+            writeln!(writer, "{line}")?;
+        }
+
+        for (id, (record, line)) in &self.modified {
+            if !seen.contains(id) && !self.deleted.contains_key(id) && belongs(record) {
+                applied.added += 1;
+                writeln!(writer, "{line}")?;
+            }
+        }
+        Ok(applied)
+    }
+}
+
+/// Whether a record belongs in a `cities<min_population>` dump.
+pub fn is_dump_city(record: &Geoname, min_population: u64) -> bool {
+    record.feature_class == FeatureClass::P
+        && (record.population.unwrap_or(0.0) >= min_population as f64
+            || SEAT_FEATURE_CODES.contains(&record.feature_code.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::io::Read;
+
+    const SOLDEU: &str = "3038999\tSoldeu\tSoldeu\t\t42.57688\t1.66769\tP\tPPL\tAD\t\t02\t\t\t\t602\t\t1832\tEurope/Andorra\t2017-11-06";
+    const EL_TARTER: &str = "3039163\tEl Tarter\tEl Tarter\t\t42.57952\t1.65362\tP\tPPL\tAD\t\t02\t\t\t\t1052\t\t1721\tEurope/Andorra\t2012-11-03";
+    const CANILLO: &str = "3041204\tCanillo\tCanillo\t\t42.5676\t1.59756\tP\tPPLA\tAD\t\t02\t\t\t\t3292\t\t1561\tEurope/Andorra\t2018-10-17";
+
+    #[test]
+    fn test_apply_delta() -> Result<()> {
+        let dir = env::temp_dir().join("waymarks_delta_test");
+        fs::create_dir_all(&dir)?;
+        let data_file = dir.join("cities500.txt");
+        let ransol = "3039654\tRansol\tRansol\t\t42.58\t1.63\tP\tPPL\tAD\t\t02\t\t\t\t600\t\t1640\tEurope/Andorra\t2019-01-01";
+        fs::write(&data_file, format!("{SOLDEU}\n{EL_TARTER}\n{ransol}\n"))?;
+
+        let renamed = SOLDEU.replace("\tSoldeu\tSoldeu\t", "\tSoldeu d'Andorra\tSoldeu\t");
+        // now below the population of a cities500 dump
+        let shrunk = ransol.replace("\t600\t", "\t300\t");
+        let hamlet = "3039999\tBordes\tBordes\t\t42.5\t1.6\tP\tPPL\tAD\t\t02\t\t\t\t20\t\t1800\tEurope/Andorra\t2024-07-01";
+        let modifications = dir.join("modifications-2024-07-01.txt");
+        fs::write(
+            &modifications,
+            format!("{renamed}\n{CANILLO}\n{hamlet}\n{shrunk}\n"),
+        )?;
+        let deletes = dir.join("deletes-2024-07-01.txt");
+        fs::write(&deletes, "3039163\tEl Tarter\tduplicate\n")?;
+
+        let delta = Delta::read(&modifications, &deletes)?;
+        assert_eq!(delta.modified.len(), 4);
+        assert_eq!(
+            delta.deleted.get(&3039163).map(String::as_str),
+            Some("El Tarter")
+        );
+
+        let applied = delta.apply(&data_file, &data_file, |record| is_dump_city(record, 500))?;
+        assert_eq!(
+            applied,
+            Applied {
+                updated: 1,
+                added: 1,
+                deleted: 2
+            }
+        );
+        let content = fs::read_to_string(&data_file)?;
+        assert_eq!(content, format!("{renamed}\n{CANILLO}\n"));
+
+        // a `.gz` target is compressed and reads back like the plain one
+        let compressed = dir.join("cities500.gz");
+        delta.apply(&data_file, &compressed, |record| is_dump_city(record, 500))?;
+        let mut content = String::new();
+        geonames::open_dump(&compressed)?.read_to_string(&mut content)?;
+        assert_eq!(content, format!("{renamed}\n{CANILLO}\n"));
+
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
@@ -1,119 +1,454 @@
 use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use colored::Colorize;
 use indicatif::{ProgressBar, ProgressStyle};
-use reqwest::Client;
+use reqwest::header::{
+    CONTENT_RANGE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, RANGE,
+};
+use reqwest::{Client, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::fs;
 use std::fs::File;
 use std::io::{self};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 use tokio::fs::OpenOptions;
 use tokio::io::AsyncWriteExt;
 use tokio::task;
 use zip::ZipArchive;
 
-async fn download_file<P>(url: &str, output_path: P) -> Result<()>
+/// What the server told about a downloaded file, kept next to it to later ask
+/// whether the file changed.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct DownloadMeta {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    etag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_modified: Option<String>,
+    /// Last time the file was downloaded or found unchanged.
+    checked_at: DateTime<Utc>,
+}
+
+impl DownloadMeta {
+    fn from_response(response: &Response) -> Self {
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        Self {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+            checked_at: Utc::now(),
+        }
+    }
+
+    /// Metadata of `path`, falling back to its modification time for files
+    /// downloaded before metadata was kept.
+    fn load(path: &Path) -> Option<Self> {
+        if let Some(meta) = fs::read_to_string(meta_path(path))
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+        {
+            return Some(meta);
+        }
+        let modified: DateTime<Utc> = fs::metadata(path).ok()?.modified().ok()?.into();
+        Some(Self {
+            etag: None,
+            last_modified: Some(modified.format(HTTP_DATE).to_string()),
+            checked_at: modified,
+        })
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        fs::write(meta_path(path), serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+const HTTP_DATE: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
+/// Downloads `url` to `output_path` through a `.part` file next to it, which
+/// is only renamed into place once its size matches the announced length and,
+/// for zip archives, every entry passes its checksum. A `.part` file left by an
+/// interrupted download is resumed with a range request.
+///
+/// With `known` metadata of the current file the download is conditional, and
+/// `false` is returned when the server reports the file unchanged.
+async fn download_file<P>(
+    url: &str,
+    output_path: P,
+    known: Option<&DownloadMeta>,
+    sha256: Option<&str>,
+) -> Result<bool>
 where
     P: AsRef<Path>,
 {
     let path = output_path.as_ref();
+    let part = part_path(path);
 
     let client = Client::builder()
         .timeout(Duration::from_secs(60))
         .build()
         .context("Failed to build HTTP client")?;
 
-    let mut response = client
-        .get(url)
-        .send()
-        .await
-        .context("Failed to send request")?;
-
-    if !response.status().is_success() {
-        anyhow::bail!("Failed to download: {}", response.status());
-    }
+    let Some((mut response, offset)) = start_download(&client, url, &part, known).await? else {
+        return Ok(false);
+    };
+    let meta = DownloadMeta::from_response(&response);
 
-    let total_size = response
-        .content_length()
-        .context("Missing content-length header")?;
+    // chunked responses do not announce their length
+    let total_size = response.content_length().map(|length| offset + length);
 
-    let pb = ProgressBar::new(total_size);
-    pb.set_style(
-        ProgressStyle::default_bar()
-            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})")
-            .unwrap()
-            .progress_chars("#>-"),
-    );
+    let pb = match total_size {
+        Some(total_size) => ProgressBar::new(total_size).with_style(
+            ProgressStyle::default_bar()
+                .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})")
+                .unwrap()
+                .progress_chars("#>-"),
+        ),
+        None => ProgressBar::new_spinner().with_style(
+            ProgressStyle::default_spinner()
+                .template("{spinner:.green} [{elapsed_precise}] {bytes} ({bytes_per_sec})")
+                .unwrap(),
+        ),
+    };
+    pb.set_position(offset);
 
     let mut file = OpenOptions::new()
         .create(true)
-        .truncate(true)
+        .append(offset > 0)
+        .truncate(offset == 0)
         .write(true)
-        .open(path)
+        .open(&part)
         .await
-        .with_context(|| format!("Failed to create file {}", path.display()))?;
+        .with_context(|| format!("Failed to create file {}", part.display()))?;
 
-    let mut downloaded: u64 = 0;
-    while let Some(chunk) = response.chunk().await? {
+    let mut downloaded = offset;
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .context("Download interrupted, run again to resume it")?
+    {
         file.write_all(&chunk).await?;
         downloaded += chunk.len() as u64;
         pb.set_position(downloaded);
     }
+    file.flush().await?;
+
+    if let Some(total_size) = total_size
+        && downloaded != total_size
+    {
+        anyhow::bail!(
+            "Download stopped after {downloaded} of {total_size} bytes, run again to resume it"
+        );
+    }
 
+    if let Some(expected) = sha256 {
+        let downloaded = part.clone();
+        let actual = task::spawn_blocking(move || sha256_hex(&downloaded)).await??;
+        if !actual.eq_ignore_ascii_case(expected.trim()) {
+            fs::remove_file(&part)?;
+            anyhow::bail!("Checksum mismatch for {url}: expected SHA-256 {expected}, got {actual}");
+        }
+    }
+
+    if is_zip(path) {
+        let archive = part.clone();
+        if let Err(err) = task::spawn_blocking(move || verify_zip(&archive)).await? {
+            fs::remove_file(&part)?;
+            return Err(err.context(format!("Downloaded archive {url} is corrupt")));
+        }
+    }
+
+    fs::rename(&part, path)
+        .with_context(|| format!("Failed to move download to {}", path.display()))?;
+    meta.save(path)?;
     pb.finish_with_message("Download complete ✅");
+    Ok(true)
+}
+
+/// Requests `url`, asking only for the bytes missing from `part` if it exists,
+/// or only for a changed file if `known` describes the current one. Returns
+/// the response and the offset its body starts at, or `None` if unchanged.
+async fn start_download(
+    client: &Client,
+    url: &str,
+    part: &Path,
+    known: Option<&DownloadMeta>,
+) -> Result<Option<(Response, u64)>> {
+    let resume_from = fs::metadata(part).map(|m| m.len()).unwrap_or(0);
+
+    let mut request = client.get(url);
+    if resume_from > 0 {
+        eprintln!("Resuming download at {resume_from} bytes");
+        request = request.header(RANGE, format!("bytes={resume_from}-"));
+    } else if let Some(known) = known {
+        if let Some(etag) = &known.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &known.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+    }
+    let response = request.send().await.context("Failed to send request")?;
+
+    let started = match response.status() {
+        StatusCode::NOT_MODIFIED => return Ok(None),
+        StatusCode::PARTIAL_CONTENT => {
+            let start = response
+                .headers()
+                .get(CONTENT_RANGE)
+                .and_then(|value| value.to_str().ok())
+                .and_then(content_range_start);
+            if start != Some(resume_from) {
+                anyhow::bail!("Server resumed the download at an unexpected position");
+            }
+            (response, resume_from)
+        }
+        // the partial file does not fit the remote one anymore, start over
+        StatusCode::RANGE_NOT_SATISFIABLE => {
+            fs::remove_file(part)?;
+            let response = client
+                .get(url)
+                .send()
+                .await
+                .context("Failed to send request")?;
+            if !response.status().is_success() {
+                anyhow::bail!("Failed to download: {}", response.status());
+            }
+            (response, 0)
+        }
+        // servers without range support send the whole file
+        status if status.is_success() => (response, 0),
+        status => anyhow::bail!("Failed to download: {status}"),
+    };
+    Ok(Some(started))
+}
+
+/// First byte position of a `Content-Range: bytes <start>-<end>/<size>` value.
+fn content_range_start(value: &str) -> Option<u64> {
+    let range = value.trim().strip_prefix("bytes ")?;
+    range.split('-').next()?.trim().parse().ok()
+}
+
+fn part_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".part");
+    PathBuf::from(name)
+}
+
+fn meta_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".meta");
+    PathBuf::from(name)
+}
+
+pub(crate) fn is_zip(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("zip"))
+}
+
+fn sha256_hex(path: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect())
+}
+
+/// Reads every entry of a zip archive, which fails on truncated archives and
+/// on entries whose CRC does not match.
+fn verify_zip(path: &Path) -> Result<()> {
+    let file = File::open(path)?;
+    let mut archive = ZipArchive::new(file)?;
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i)?;
+        io::copy(&mut entry, &mut io::sink())
+            .with_context(|| format!("Corrupt entry {} in {}", entry.name(), path.display()))?;
+    }
     Ok(())
 }
 
-pub(crate) async fn ensure_file<P>(url: &str, output_path: P) -> Result<()>
+/// How a downloaded file is kept current and checked.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct DownloadPolicy<'a> {
+    /// Ask the server for a newer file once it was last checked this many
+    /// days ago.
+    pub max_age_days: Option<u64>,
+    /// Expected SHA-256 of the file as hex.
+    pub sha256: Option<&'a str>,
+}
+
+/// Downloads `url` unless `output_path` already holds it. Files last checked
+/// longer ago than the policy allows are refreshed if the server has a newer
+/// one. Returns whether new content was downloaded.
+pub(crate) async fn ensure_file<P>(
+    url: &str,
+    output_path: P,
+    policy: &DownloadPolicy<'_>,
+) -> Result<bool>
 where
     P: AsRef<Path>,
 {
     let path = output_path.as_ref();
 
     if path.exists() {
-        println!("File {} already exists, skipping download.", path.display());
-        return Ok(());
+        // a truncated archive has no central directory, so this is cheap to
+        // tell; the entries themselves were checked when it was downloaded
+        let readable = !is_zip(path) || matches!(File::open(path).map(ZipArchive::new), Ok(Ok(_)));
+        if !readable {
+            eprintln!(
+                "{}",
+                format!("File {} is corrupt, downloading it again", path.display()).yellow()
+            );
+            fs::remove_file(path)?;
+        } else if is_stale(path, policy.max_age_days) {
+            return refresh_file(url, path, policy).await;
+        } else {
+            eprintln!("File {} already exists, skipping download.", path.display());
+            return Ok(false);
+        }
     }
 
-    println!("Downloading {url} → {}...", path.display());
+    eprintln!("Downloading {url} → {}...", path.display());
     if let Some(parent) = path.parent() {
         fs::create_dir_all(parent)
             .with_context(|| format!("Failed to create directory {}", parent.display()))?;
     }
-    download_file(url, output_path).await?;
-    Ok(())
+    download_file(url, path, None, policy.sha256).await
 }
 
-pub(crate) async fn unzip_file(zip_path: &str, output_dir: &str) -> anyhow::Result<()> {
-    let zip_path = zip_path.to_string();
-    let output_dir = output_dir.to_string();
+/// Downloads `url` again if the server has a different file than the one at
+/// `output_path`, asking with the ETag and Last-Modified it sent last time.
+/// Returns whether new content was downloaded.
+pub(crate) async fn refresh_file<P>(
+    url: &str,
+    output_path: P,
+    policy: &DownloadPolicy<'_>,
+) -> Result<bool>
+where
+    P: AsRef<Path>,
+{
+    let path = output_path.as_ref();
+    let Some(known) = DownloadMeta::load(path) else {
+        eprintln!("Downloading {url} → {}...", path.display());
+        return download_file(url, path, None, policy.sha256).await;
+    };
 
-    task::spawn_blocking(move || -> Result<()> {
-        let file = File::open(&zip_path)?;
-        let mut archive = ZipArchive::new(file)?;
+    eprintln!("Checking {url} for updates...");
+    let updated = download_file(url, path, Some(&known), policy.sha256).await?;
+    if updated {
+        eprintln!("{}", format!("Updated {}", path.display()).green());
+    } else {
+        DownloadMeta {
+            checked_at: Utc::now(),
+            ..known
+        }
+        .save(path)?;
+        eprintln!("File {} is up to date", path.display());
+    }
+    Ok(updated)
+}
 
-        for i in 0..archive.len() {
-            let mut zipped_file = archive.by_index(i)?;
-            let outpath = std::path::Path::new(&output_dir).join(zipped_file.name());
-            if outpath.exists() {
-                println!("Skipping {}, already exists", outpath.display());
-                continue;
-            }
+fn is_stale(path: &Path, max_age_days: Option<u64>) -> bool {
+    let Some(days) = max_age_days else {
+        return false;
+    };
+    DownloadMeta::load(path)
+        .is_some_and(|meta| Utc::now() - meta.checked_at > chrono::Duration::days(days as i64))
+}
 
-            if zipped_file.is_dir() {
-                std::fs::create_dir_all(&outpath)?;
-            } else {
-                if let Some(p) = outpath.parent() {
-                    std::fs::create_dir_all(p)?;
-                }
-                let mut outfile = File::create(&outpath)?;
-                io::copy(&mut zipped_file, &mut outfile)?;
-                println!("Extracted {}", outpath.display());
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::io::Write;
+    use zip::ZipWriter;
+    use zip::write::SimpleFileOptions;
+
+    #[test]
+    fn test_content_range_start() {
+        assert_eq!(content_range_start("bytes 100-999/1000"), Some(100));
+        assert_eq!(content_range_start("bytes 0-0/*"), Some(0));
+        assert_eq!(content_range_start("bytes */1000"), None);
+        assert_eq!(
+            part_path(Path::new("downloads/cities500.zip")),
+            PathBuf::from("downloads/cities500.zip.part")
+        );
+    }
+
+    #[test]
+    fn test_download_meta() -> Result<()> {
+        let path = env::temp_dir().join("waymarks_meta_test.txt");
+        fs::write(&path, "data")?;
+        let _ = fs::remove_file(meta_path(&path));
+
+        // files from before metadata was kept are judged by their mtime
+        let meta = DownloadMeta::load(&path).unwrap();
+        assert!(meta.etag.is_none());
+        assert!(meta.last_modified.unwrap().ends_with(" GMT"));
+        assert!(!is_stale(&path, Some(1)));
+        assert!(!is_stale(&path, None));
+
+        DownloadMeta {
+            etag: Some("\"abc\"".to_string()),
+            last_modified: None,
+            checked_at: Utc::now() - chrono::Duration::days(3),
         }
+        .save(&path)?;
+        assert_eq!(
+            DownloadMeta::load(&path).unwrap().etag.as_deref(),
+            Some("\"abc\"")
+        );
+        assert!(is_stale(&path, Some(2)));
+        assert!(!is_stale(&path, Some(5)));
 
+        fs::remove_file(meta_path(&path))?;
+        fs::remove_file(path)?;
         Ok(())
-    })
-    .await??;
+    }
 
-    Ok(())
+    #[test]
+    fn test_sha256_hex() -> Result<()> {
+        let path = env::temp_dir().join("waymarks_sha256_test.txt");
+        fs::write(&path, "abc")?;
+        assert_eq!(
+            sha256_hex(&path)?,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    fn write_zip(path: &Path, entries: &[(&str, &str)]) -> Result<()> {
+        let mut zip = ZipWriter::new(File::create(path)?);
+        for (name, content) in entries {
+            zip.start_file(*name, SimpleFileOptions::default())?;
+            zip.write_all(content.as_bytes())?;
+        }
+        zip.finish()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_zip() -> Result<()> {
+        let path = env::temp_dir().join("waymarks_verify_test.zip");
+        let content = "3041563\tAndorra la Vella\n".repeat(100);
+        write_zip(&path, &[("cities.txt", &content)])?;
+        assert!(verify_zip(&path).is_ok());
+
+        // cut off the central directory, as an interrupted download would
+        let content = fs::read(&path)?;
+        fs::write(&path, &content[..content.len() / 2])?;
+        assert!(verify_zip(&path).is_err());
+
+        fs::remove_file(path)?;
+        Ok(())
+    }
 }
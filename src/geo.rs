@@ -0,0 +1,79 @@
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+/// Great-circle distance between two points given in degrees.
+pub fn haversine_km(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let d_lat = (lat2 - lat1).to_radians();
+    let d_lon = (lon2 - lon1).to_radians();
+    let a = (d_lat / 2.0).sin().powi(2)
+        + lat1.to_radians().cos() * lat2.to_radians().cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+}
+
+/// Adds points along straight segments so consecutive points are at most
+/// `max_step_km` apart. Meant for coarse tracks with short segments, where
+/// interpolating the coordinates linearly is close enough. Segments crossing
+/// the ±180° meridian are interpolated across it, not around the globe.
+pub fn densify(points: &[(f64, f64)], max_step_km: f64) -> Vec<(f64, f64)> {
+    let mut dense = Vec::with_capacity(points.len());
+    for pair in points.windows(2) {
+        let ((lat1, lon1), (lat2, lon2)) = (pair[0], pair[1]);
+        let distance = haversine_km(lat1, lon1, lat2, lon2);
+        let steps = if max_step_km > 0.0 {
+            (distance / max_step_km).ceil().max(1.0) as usize
+        } else {
+            1
+        };
+        let d_lon = match lon2 - lon1 {
+            d if d > 180.0 => d - 360.0,
+            d if d < -180.0 => d + 360.0,
+            d => d,
+        };
+        for i in 0..steps {
+            let t = i as f64 / steps as f64;
+            let lon = match lon1 + d_lon * t {
+                lon if lon > 180.0 => lon - 360.0,
+                lon if lon < -180.0 => lon + 360.0,
+                lon => lon,
+            };
+            dense.push((lat1 + (lat2 - lat1) * t, lon));
+        }
+    }
+    dense.extend(points.last());
+    dense
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_haversine_km() {
+        assert_eq!(haversine_km(52.52, 13.405, 52.52, 13.405), 0.0);
+
+        // Berlin → Munich is roughly 504 km
+        let d = haversine_km(52.52, 13.405, 48.13743, 11.57549);
+        assert!((d - 504.0).abs() < 2.0, "unexpected distance {d}");
+    }
+
+    #[test]
+    fn test_densify() {
+        assert!(densify(&[], 1.0).is_empty());
+        assert_eq!(densify(&[(1.0, 2.0)], 1.0), vec![(1.0, 2.0)]);
+
+        // 0.1° of latitude is about 11.1 km
+        let dense = densify(&[(45.0, 7.0), (45.1, 7.0)], 2.0);
+        assert_eq!(dense.len(), 7);
+        assert_eq!(dense.first(), Some(&(45.0, 7.0)));
+        assert_eq!(dense.last(), Some(&(45.1, 7.0)));
+        assert!(
+            dense
+                .windows(2)
+                .all(|w| haversine_km(w[0].0, w[0].1, w[1].0, w[1].1) <= 2.0)
+        );
+
+        // across the ±180° meridian, about 2.1 km
+        let dense = densify(&[(-16.8, 179.99), (-16.8, -179.99)], 0.5);
+        assert_eq!(dense.len(), 6);
+        assert!(dense.iter().all(|p| p.1.abs() >= 179.99));
+    }
+}
@@ -1,10 +1,19 @@
-use anyhow::Result;
+use crate::geo::haversine_km;
+use crate::text::fold_diacritics;
+use anyhow::{Context, Result};
 use chrono::NaiveDate;
-use csv::ReaderBuilder;
+use csv::{ReaderBuilder, StringRecord};
+use flate2::read::{DeflateDecoder, MultiGzDecoder};
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
 use std::path::Path;
+use std::str::FromStr;
+use zip::{CompressionMethod, ZipArchive};
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Geoname {
@@ -34,6 +43,7 @@ pub struct Geoname {
     pub modification_date: NaiveDate,
 }
 
+/// A row of `countryInfo.txt`.
 #[derive(Debug, Deserialize, PartialEq)]
 pub struct CountryInfo {
     pub iso: String,
@@ -41,6 +51,42 @@ pub struct CountryInfo {
     pub iso_numeric: String,
     pub fips: Option<String>,
     pub country: String,
+    pub capital: Option<String>,
+    pub area_sq_km: Option<f64>,
+    pub population: Option<u64>,
+    pub continent: Continent,
+    pub tld: Option<String>,
+    pub currency_code: Option<String>,
+    pub currency_name: Option<String>,
+    pub phone: Option<String>,
+    pub postal_code_format: Option<String>,
+    pub postal_code_regex: Option<String>,
+    /// Language tags, most spoken first.
+    #[serde(deserialize_with = "comma_list::deserialize")]
+    pub languages: Vec<String>,
+    pub geonameid: Option<i64>,
+    /// ISO codes of the countries sharing a border.
+    #[serde(deserialize_with = "comma_list::deserialize")]
+    pub neighbours: Vec<String>,
+    pub equivalent_fips_code: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Continent {
+    #[serde(rename = "AF")]
+    Africa,
+    #[serde(rename = "AN")]
+    Antarctica,
+    #[serde(rename = "AS")]
+    Asia,
+    #[serde(rename = "EU")]
+    Europe,
+    #[serde(rename = "NA")]
+    NorthAmerica,
+    #[serde(rename = "OC")]
+    Oceania,
+    #[serde(rename = "SA")]
+    SouthAmerica,
 }
 
 #[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Hash)]
@@ -61,25 +107,367 @@ where
     T: DeserializeOwned,
     P: AsRef<Path>,
 {
-    let mut rdr = ReaderBuilder::new()
+    records(open_dump(path)?).collect()
+}
+
+/// Deserializes the rows of a GeoNames TSV stream one at a time, skipping
+/// `#` comments.
+pub fn records<T, R>(reader: R) -> impl Iterator<Item = Result<T>>
+where
+    T: DeserializeOwned,
+    R: Read,
+{
+    ReaderBuilder::new()
         .delimiter(b'\t')
         .has_headers(false)
         .comment(Some(b'#'))
-        .from_path(path)
-        .map_err(|e| anyhow::anyhow!("Failed to read TSV file: {}", e))?;
+        .from_reader(reader)
+        .into_deserialize()
+        .map(|result| result.map_err(|e| anyhow::anyhow!("Failed to deserialize record: {}", e)))
+}
+
+/// Conditions on GeoNames rows. They are checked on the raw columns while
+/// reading, so rows that do not match are never deserialized.
+#[derive(Debug, Default, Clone)]
+pub struct RecordFilter {
+    /// Accepted feature classes, any when empty.
+    pub feature_classes: Vec<FeatureClass>,
+}
+
+/// A latitude/longitude rectangle, in degrees. When `min_lon` is greater than
+/// `max_lon` the rectangle crosses the ±180° meridian.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bounds {
+    pub min_lat: f64,
+    pub max_lat: f64,
+    pub min_lon: f64,
+    pub max_lon: f64,
+}
+
+/// Whether to deserialize the `alternatenames` column, by far the largest one,
+/// into [`Geoname::alternatenames`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlternateNames {
+    Keep,
+    Skip,
+}
 
-    let mut records = Vec::new();
-    for result in rdr.deserialize() {
-        let record: T =
-            result.map_err(|e| anyhow::anyhow!("Failed to deserialize record: {}", e))?;
-        records.push(record);
+/// One row of a GeoNames dump, kept as read until it is deserialized.
+#[derive(Debug, Clone)]
+pub struct Row {
+    line: String,
+}
+
+impl RecordFilter {
+    /// Checks the raw columns of a row, see [`Row::columns`].
+    pub fn matches(&self, columns: &[&str]) -> bool {
+        let class = columns.get(6).copied().unwrap_or_default();
+        self.feature_classes.is_empty()
+            || self
+                .feature_classes
+                .iter()
+                .any(|wanted| wanted.as_str() == class)
     }
-    Ok(records)
 }
 
-impl fmt::Display for FeatureClass {
+impl Bounds {
+    pub fn contains(&self, lat: f64, lon: f64) -> bool {
+        let within_lon = if self.min_lon <= self.max_lon {
+            (self.min_lon..=self.max_lon).contains(&lon)
+        } else {
+            lon >= self.min_lon || lon <= self.max_lon
+        };
+        (self.min_lat..=self.max_lat).contains(&lat) && within_lon
+    }
+}
+
+impl Row {
+    pub fn new(mut line: String) -> Self {
+        let len = line.trim_end_matches(['\n', '\r']).len();
+        line.truncate(len);
+        Self { line }
+    }
+
+    /// The row without its line break.
+    pub fn line(&self) -> &str {
+        &self.line
+    }
+
+    pub fn columns(&self) -> Vec<&str> {
+        self.line.split('\t').collect()
+    }
+
+    pub fn to_geoname(&self, alternate_names: AlternateNames) -> Result<Geoname> {
+        let mut columns = self.columns();
+        if alternate_names == AlternateNames::Skip
+            && let Some(names) = columns.get_mut(3)
+        {
+            *names = "";
+        }
+        StringRecord::from(columns)
+            .deserialize(None)
+            .with_context(|| format!("Failed to deserialize record '{}'", self.line))
+    }
+}
+
+/// Streams the rows of a GeoNames dump that match `filter`, skipping `#`
+/// comments and blank lines.
+pub fn rows<'a, R>(reader: R, filter: &'a RecordFilter) -> impl Iterator<Item = Result<Row>> + 'a
+where
+    R: BufRead + 'a,
+{
+    reader
+        .lines()
+        .filter(|line| {
+            line.as_ref()
+                .map_or(true, |line| !line.is_empty() && !line.starts_with('#'))
+        })
+        .map(|line| Ok(Row::new(line?)))
+        .filter(move |row| match row {
+            Ok(row) => filter.matches(&row.columns()),
+            Err(_) => true,
+        })
+}
+
+/// Opens a GeoNames file for streaming: the data entry of a zip archive (the
+/// one named like the archive, `cities500.txt` in `cities500.zip`), a gzip
+/// file, or plain text. Nothing is extracted to disk.
+pub fn open_dump<P>(path: P) -> Result<Box<dyn BufRead + Send>>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+
+    Ok(match extension.as_str() {
+        "zip" => Box::new(BufReader::new(zip_entry_reader(path, file)?)),
+        "gz" => Box::new(BufReader::new(MultiGzDecoder::new(file))),
+        _ => Box::new(BufReader::new(file)),
+    })
+}
+
+/// Reads the data entry straight from the archive file. The zip reader would
+/// borrow the archive, so this seeks to the entry's data and inflates it
+/// itself; the archive was verified when it was downloaded.
+fn zip_entry_reader(path: &Path, file: File) -> Result<Box<dyn Read + Send>> {
+    let mut archive = ZipArchive::new(file)
+        .with_context(|| format!("Failed to read archive {}", path.display()))?;
+    let wanted = path.with_extension("txt");
+    let wanted = wanted
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default();
+    let name = if archive.index_for_name(wanted).is_some() {
+        wanted.to_string()
+    } else {
+        let mut data = archive
+            .file_names()
+            .filter(|name| !name.ends_with('/') && !name.eq_ignore_ascii_case("readme.txt"));
+        match (data.next(), data.next()) {
+            (Some(name), None) => name.to_string(),
+            _ => anyhow::bail!("Archive {} has no entry {wanted}", path.display()),
+        }
+    };
+
+    let (compression, start, size) = {
+        let entry = archive.by_name(&name)?;
+        (
+            entry.compression(),
+            entry.data_start(),
+            entry.compressed_size(),
+        )
+    };
+    let mut file = archive.into_inner();
+    file.seek(SeekFrom::Start(start))?;
+    let data = file.take(size);
+    Ok(match compression {
+        CompressionMethod::Stored => Box::new(data),
+        CompressionMethod::Deflated => Box::new(DeflateDecoder::new(data)),
+        other => anyhow::bail!("Unsupported compression {other} in {}", path.display()),
+    })
+}
+
+/// Key under which names are compared: trimmed, without accents and lowercase.
+pub fn normalize_name(name: &str) -> String {
+    fold_diacritics(name.trim()).to_lowercase()
+}
+
+impl Continent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Continent::Africa => "Africa",
+            Continent::Antarctica => "Antarctica",
+            Continent::Asia => "Asia",
+            Continent::Europe => "Europe",
+            Continent::NorthAmerica => "North America",
+            Continent::Oceania => "Oceania",
+            Continent::SouthAmerica => "South America",
+        }
+    }
+}
+
+impl FromStr for Continent {
+    type Err = String;
+
+    /// Parses a two-letter code like `EU` or a name like `north america`,
+    /// ignoring case.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let wanted = s.trim().replace(['-', '_'], " ");
+        [
+            (Continent::Africa, "AF"),
+            (Continent::Antarctica, "AN"),
+            (Continent::Asia, "AS"),
+            (Continent::Europe, "EU"),
+            (Continent::NorthAmerica, "NA"),
+            (Continent::Oceania, "OC"),
+            (Continent::SouthAmerica, "SA"),
+        ]
+        .into_iter()
+        .find(|(continent, code)| {
+            wanted.eq_ignore_ascii_case(code) || wanted.eq_ignore_ascii_case(continent.name())
+        })
+        .map(|(continent, _)| continent)
+        .ok_or_else(|| format!("unknown continent '{s}'"))
+    }
+}
+
+impl fmt::Display for Continent {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let symbol = match self {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl Geoname {
+    /// Relative importance of a populated place by its feature code:
+    /// capitals first, then administrative seats, then everything else.
+    pub fn feature_rank(&self) -> u8 {
+        match self.feature_code.as_str() {
+            "PPLC" => 0,
+            "PPLA" => 1,
+            "PPLA2" => 2,
+            "PPLA3" => 3,
+            "PPLA4" | "PPLA5" => 4,
+            "PPLG" => 5,
+            _ => 6,
+        }
+    }
+
+    pub fn alternate_names(&self) -> impl Iterator<Item = &str> {
+        self.alternatenames
+            .as_deref()
+            .unwrap_or_default()
+            .split(',')
+            .filter(|n| !n.is_empty())
+    }
+
+    /// Mountains, peaks and volcanoes, excluding hills and ranges.
+    pub fn is_summit(&self) -> bool {
+        self.feature_class == FeatureClass::T
+            && SUMMIT_FEATURE_CODES.contains(&self.feature_code.as_str())
+    }
+
+    /// Populated places and mountain huts, what can be recorded as a city.
+    pub fn is_locality(&self) -> bool {
+        self.feature_class == FeatureClass::P
+            || (self.feature_class == FeatureClass::S
+                && HUT_FEATURE_CODES.contains(&self.feature_code.as_str()))
+    }
+
+    /// Surveyed elevation, falling back to the digital elevation model.
+    pub fn elevation_m(&self) -> Option<i32> {
+        self.elevation.or_else(|| {
+            self.dem
+                .filter(|dem| *dem != NO_DEM_DATA)
+                .map(|dem| dem.round() as i32)
+        })
+    }
+}
+
+pub const SUMMIT_FEATURE_CODES: &[&str] = &["MT", "PK", "PKS", "VLC"];
+
+pub const HUT_FEATURE_CODES: &[&str] = &["HUT", "HUTS"];
+
+/// GeoNames marks points without elevation model data (e.g. at sea) with -9999.
+const NO_DEM_DATA: f64 = -9999.0;
+
+/// Groups every record matching one of `names`, keyed by the normalized requested
+/// name. Records matching on their name or asciiname win; alternate names are only
+/// used for names that have no such match.
+pub fn match_names<I>(records: I, names: &[String]) -> HashMap<String, Vec<Geoname>>
+where
+    I: IntoIterator<Item = Geoname>,
+{
+    // normalize requested names only once
+    let mut res: HashMap<String, Vec<Geoname>> = names
+        .iter()
+        .map(|n| (normalize_name(n), Vec::new()))
+        .collect();
+    let mut alternates: HashMap<String, Vec<Geoname>> = HashMap::new();
+
+    for record in records {
+        let name = normalize_name(&record.name);
+        let asciiname = record.asciiname.as_deref().map(normalize_name);
+
+        // collect every same-named place, the caller decides which one is meant
+        let key = if res.contains_key(&name) {
+            Some(name)
+        } else {
+            asciiname.filter(|k| res.contains_key(k))
+        };
+        if let Some(key) = key {
+            res.get_mut(&key).unwrap().push(record);
+        } else {
+            let key = record
+                .alternate_names()
+                .map(normalize_name)
+                .find(|k| res.contains_key(k));
+            if let Some(key) = key {
+                alternates.entry(key).or_default().push(record);
+            }
+        }
+    }
+
+    for (key, records) in alternates {
+        let entry = res.get_mut(&key).unwrap();
+        if entry.is_empty() {
+            *entry = records;
+        }
+    }
+
+    res
+}
+
+/// Orders same-named candidates so the most likely match comes first: nearest to
+/// `near` when given, otherwise by feature rank, then population, then elevation.
+pub fn rank_candidates(candidates: &mut [Geoname], near: Option<(f64, f64)>) {
+    match near {
+        Some((lat, lon)) => candidates.sort_by(|a, b| {
+            let da = haversine_km(lat, lon, a.latitude, a.longitude);
+            let db = haversine_km(lat, lon, b.latitude, b.longitude);
+            da.partial_cmp(&db).unwrap_or(Ordering::Equal)
+        }),
+        None => candidates.sort_by(|a, b| {
+            a.feature_rank()
+                .cmp(&b.feature_rank())
+                .then_with(|| {
+                    b.population
+                        .unwrap_or(0.0)
+                        .partial_cmp(&a.population.unwrap_or(0.0))
+                        .unwrap_or(Ordering::Equal)
+                })
+                .then_with(|| b.elevation_m().cmp(&a.elevation_m()))
+        }),
+    }
+}
+
+impl FeatureClass {
+    pub fn as_str(&self) -> &'static str {
+        match self {
             FeatureClass::A => "A",
             FeatureClass::H => "H",
             FeatureClass::L => "L",
@@ -89,8 +477,13 @@ impl fmt::Display for FeatureClass {
             FeatureClass::T => "T",
             FeatureClass::U => "U",
             FeatureClass::V => "V",
-        };
-        write!(f, "{symbol}")
+        }
+    }
+}
+
+impl fmt::Display for FeatureClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
     }
 }
 
@@ -117,6 +510,22 @@ mod date_format {
     }
 }
 
+mod comma_list {
+    use serde::{Deserialize, Deserializer};
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        Ok(s.split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(str::to_string)
+            .collect())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::{env, path::PathBuf};
@@ -171,12 +580,129 @@ mod tests {
         assert_eq!(countries[0].iso_numeric, "020");
         assert_eq!(countries[0].fips, Some("AN".to_string()));
         assert_eq!(countries[0].country, "Andorra");
+        assert_eq!(countries[0].capital.as_deref(), Some("Andorra la Vella"));
+        assert_eq!(countries[0].area_sq_km, Some(468.0));
+        assert_eq!(countries[0].population, Some(77006));
+        assert_eq!(countries[0].continent, Continent::Europe);
+        assert_eq!(countries[0].tld.as_deref(), Some(".ad"));
+        assert_eq!(countries[0].currency_code.as_deref(), Some("EUR"));
+        assert_eq!(countries[0].postal_code_format.as_deref(), Some("AD###"));
+        assert_eq!(countries[0].languages, ["ca"]);
+        assert_eq!(countries[0].geonameid, Some(3041565));
+        assert_eq!(countries[0].neighbours, ["ES", "FR"]);
+        assert_eq!(countries[0].equivalent_fips_code, None);
 
         assert_eq!(countries[1].iso, "AE");
         assert_eq!(countries[1].iso3, "ARE");
         assert_eq!(countries[1].iso_numeric, "784");
         assert_eq!(countries[1].fips, Some("AE".to_string()));
         assert_eq!(countries[1].country, "United Arab Emirates");
+        assert_eq!(countries[1].continent, Continent::Asia);
+        assert_eq!(countries[1].languages, ["ar-AE", "fa", "en", "hi", "ur"]);
+
+        // every row of the sample parses, including ones without postal codes
+        // or neighbours
+        let afghanistan = countries.iter().find(|c| c.iso == "AF").unwrap();
+        assert_eq!(afghanistan.postal_code_regex, None);
+        assert_eq!(afghanistan.neighbours.len(), 6);
+        assert!(countries.iter().all(|c| c.geonameid.is_some()));
+
+        assert_eq!("eu".parse(), Ok(Continent::Europe));
+        assert_eq!("North-America".parse(), Ok(Continent::NorthAmerica));
+        assert!("Atlantis".parse::<Continent>().is_err());
+    }
+
+    #[test]
+    fn test_filtered_rows() -> Result<()> {
+        let sample = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data/cities_sample.txt");
+        let peak = "3039999\tPic de Coma Pedrosa\tPic de Coma Pedrosa\tComa Pedrosa\t42.59\t1.44\tT\tPK\tAD\t\t\t\t\t\t0\t2942\t2900\tEurope/Andorra\t2020-01-01";
+        let dump = format!("# comment\n{}{peak}\n\n", std::fs::read_to_string(&sample)?);
+
+        let names = |filter: &RecordFilter| -> Result<Vec<String>> {
+            rows(dump.as_bytes(), filter)
+                .map(|row| Ok(row?.to_geoname(AlternateNames::Skip)?.name))
+                .collect()
+        };
+
+        assert_eq!(names(&RecordFilter::default())?.len(), 7);
+        let summits = RecordFilter {
+            feature_classes: vec![FeatureClass::T],
+        };
+        assert_eq!(names(&summits)?, ["Pic de Coma Pedrosa"]);
+        let places = RecordFilter {
+            feature_classes: vec![FeatureClass::P, FeatureClass::S],
+        };
+        assert_eq!(names(&places)?.len(), 6);
+
+        let row = Row::new(format!("{peak}\n"));
+        assert_eq!(row.line(), peak);
+        let full = row.to_geoname(AlternateNames::Keep)?;
+        assert_eq!(full.alternatenames.as_deref(), Some("Coma Pedrosa"));
+        assert_eq!(full.elevation_m(), Some(2942));
+        assert_eq!(row.to_geoname(AlternateNames::Skip)?.alternatenames, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_bounds() {
+        let andorra = Bounds {
+            min_lat: 42.0,
+            max_lat: 43.0,
+            min_lon: 1.4,
+            max_lon: 1.8,
+        };
+        assert!(andorra.contains(42.5, 1.6));
+        assert!(!andorra.contains(42.5, 2.0));
+        assert!(!andorra.contains(41.5, 1.6));
+
+        // Taveuni, Fiji, straddles the ±180° meridian
+        let taveuni = Bounds {
+            min_lat: -17.2,
+            max_lat: -16.6,
+            min_lon: 179.8,
+            max_lon: -179.8,
+        };
+        assert!(taveuni.contains(-16.8, 179.9));
+        assert!(taveuni.contains(-16.8, -179.9));
+        assert!(!taveuni.contains(-16.8, 0.0));
+        assert!(!taveuni.contains(-16.8, 179.7));
+    }
+
+    #[test]
+    fn test_open_dump_formats() -> Result<()> {
+        use flate2::{Compression, write::GzEncoder};
+        use std::io::Write;
+        use zip::write::{SimpleFileOptions, ZipWriter};
+
+        let sample = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data/cities_sample.txt");
+        let content = std::fs::read(&sample)?;
+        let expected: Vec<Geoname> = read_tsv(&sample)?;
+        let dir = env::temp_dir().join("waymarks_open_dump_test");
+        std::fs::create_dir_all(&dir)?;
+
+        for method in [CompressionMethod::Deflated, CompressionMethod::Stored] {
+            let archive = dir.join("cities_sample.zip");
+            let mut zip = ZipWriter::new(File::create(&archive)?);
+            let options = SimpleFileOptions::default().compression_method(method);
+            zip.start_file("readme.txt", options)?;
+            zip.write_all(b"not a dump")?;
+            zip.start_file("cities_sample.txt", options)?;
+            zip.write_all(&content)?;
+            zip.finish()?;
+
+            let records: Vec<Geoname> = records(open_dump(&archive)?).collect::<Result<_>>()?;
+            assert_eq!(records, expected);
+        }
+
+        let gzipped = dir.join("cities_sample.txt.gz");
+        let mut gz = GzEncoder::new(File::create(&gzipped)?, Compression::default());
+        gz.write_all(&content)?;
+        gz.finish()?;
+        let mut streamed = records::<Geoname, _>(open_dump(&gzipped)?);
+        assert_eq!(streamed.next().transpose()?.as_ref(), expected.first());
+
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
     }
 
     #[test]
@@ -236,4 +762,56 @@ mod tests {
             NaiveDate::from_ymd_opt(2017, 11, 6).unwrap()
         );
     }
+
+    #[test]
+    fn test_rank_candidates() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data/cities_sample.txt");
+        let mut geonames: Vec<Geoname> = read_tsv(&path).expect("Failed to read geoname TSV");
+
+        // the parish seat comes first, then plain villages by population
+        rank_candidates(&mut geonames, None);
+        assert_eq!(geonames[0].name, "Sant Julià de Lòria");
+        assert_eq!(geonames[1].name, "Pas de la Casa");
+        assert_eq!(geonames[2].name, "Vila");
+
+        rank_candidates(&mut geonames, Some((42.58, 1.66)));
+        assert_eq!(geonames[0].name, "El Tarter");
+        assert_eq!(geonames[1].name, "Soldeu");
+    }
+
+    #[test]
+    fn test_match_names() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data/cities_sample.txt");
+        let geonames: Vec<Geoname> = read_tsv(&path).expect("Failed to read geoname TSV");
+
+        let names = vec![
+            "SOLDEU".to_string(),
+            "sant julia de loria".to_string(),
+            "Nowhere".to_string(),
+        ];
+        let matches = match_names(geonames, &names);
+
+        assert_eq!(matches.len(), 3);
+        assert_eq!(matches["soldeu"][0].geonameid, 3038999);
+        assert_eq!(
+            matches["sant julia de loria"][0].name,
+            "Sant Julià de Lòria"
+        );
+        assert!(matches["nowhere"].is_empty());
+    }
+
+    #[test]
+    fn test_elevation_falls_back_to_dem() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data/cities_sample.txt");
+        let mut geonames: Vec<Geoname> = read_tsv(&path).expect("Failed to read geoname TSV");
+
+        let pas_de_la_casa = geonames.pop().unwrap();
+        assert_eq!(pas_de_la_casa.elevation_m(), Some(2050));
+        assert_eq!(geonames[0].elevation_m(), Some(1318));
+        assert!(geonames.iter().all(|g| !g.is_summit()));
+        assert!(geonames.iter().all(|g| g.is_locality()));
+
+        geonames[0].dem = Some(NO_DEM_DATA);
+        assert_eq!(geonames[0].elevation_m(), None);
+    }
 }
@@ -0,0 +1,164 @@
+use crate::geo::{densify, haversine_km};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::{Reader, XmlVersion};
+use std::path::Path;
+
+/// Track points of a GPX file at about an even spacing, and the time range the
+/// track was recorded in.
+#[derive(Debug, Default)]
+pub struct Track {
+    pub points: Vec<(f64, f64)>,
+    pub start: Option<DateTime<Utc>>,
+    pub end: Option<DateTime<Utc>>,
+}
+
+/// Streams the `trkpt` elements of a GPX file. Consecutive points closer than
+/// `spacing_km` to the last kept one are skipped, which keeps long, densely
+/// sampled tracks small without losing their shape. Segments longer than that
+/// get points added along them, so places are not missed between the few
+/// vertices of a sparse track.
+pub fn read_track<P>(path: P, spacing_km: f64) -> Result<Track>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let mut reader = Reader::from_file(path)
+        .with_context(|| format!("Failed to open GPX file {}", path.display()))?;
+
+    let mut track = Track::default();
+    let mut buf = Vec::new();
+    let mut in_point = false;
+    let mut in_time = false;
+
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(e) if e.local_name().as_ref() == "trkpt" => {
+                track.push(point(&e)?, spacing_km);
+                in_point = true;
+            }
+            Event::Empty(e) if e.local_name().as_ref() == "trkpt" => {
+                track.push(point(&e)?, spacing_km);
+            }
+            Event::End(e) if e.local_name().as_ref() == "trkpt" => in_point = false,
+            Event::Start(e) if in_point && e.local_name().as_ref() == "time" => in_time = true,
+            Event::End(e) if e.local_name().as_ref() == "time" => in_time = false,
+            Event::Text(e) if in_time => {
+                let time = DateTime::parse_from_rfc3339(e.xml10_content().trim())
+                    .with_context(|| format!("Invalid track point time in {}", path.display()))?
+                    .with_timezone(&Utc);
+                track.start = Some(track.start.map_or(time, |start| start.min(time)));
+                track.end = Some(track.end.map_or(time, |end| end.max(time)));
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    if track.points.is_empty() {
+        anyhow::bail!("No track points found in {}", path.display());
+    }
+    track.points = densify(&track.points, spacing_km);
+    Ok(track)
+}
+
+impl Track {
+    fn push(&mut self, (lat, lon): (f64, f64), min_spacing_km: f64) {
+        if let Some(&(last_lat, last_lon)) = self.points.last()
+            && haversine_km(last_lat, last_lon, lat, lon) < min_spacing_km
+        {
+            return;
+        }
+        self.points.push((lat, lon));
+    }
+}
+
+fn point(e: &BytesStart) -> Result<(f64, f64)> {
+    let coordinate = |name: &str| -> Result<f64> {
+        let value = e
+            .try_get_attribute(name)?
+            .with_context(|| format!("Track point without {name}"))?
+            .normalized_value(XmlVersion::Implicit1_0)?;
+        value
+            .trim()
+            .parse()
+            .with_context(|| format!("Invalid track point {name} '{value}'"))
+    };
+    Ok((coordinate("lat")?, coordinate("lon")?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::{PlaceKind, SearchIndex};
+    use std::{env, fs, path::PathBuf};
+
+    #[test]
+    fn test_read_track() -> Result<()> {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data/track_sample.gpx");
+
+        let track = read_track(&path, 0.0)?;
+        assert_eq!(track.points.len(), 5);
+        assert_eq!(track.points[0], (42.5766, 1.6672));
+        assert_eq!(
+            track.start,
+            Some("2024-07-01T07:58:00Z".parse::<DateTime<Utc>>()?)
+        );
+        assert_eq!(
+            track.end,
+            Some("2024-07-01T12:30:00Z".parse::<DateTime<Utc>>()?)
+        );
+
+        // the two points right at the start of the track are 50 m apart
+        let thinned = read_track(&path, 0.1)?;
+        assert_eq!(thinned.points[0], (42.5766, 1.6672));
+        assert!(!thinned.points.contains(&track.points[1]));
+        assert!(
+            thinned
+                .points
+                .windows(2)
+                .all(|pair| haversine_km(pair[0].0, pair[0].1, pair[1].0, pair[1].1) <= 0.1 + 1e-9)
+        );
+
+        let missing = env::temp_dir().join("waymarks_missing.gpx");
+        assert!(read_track(&missing, 0.0).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_sparse_track_finds_places_mid_segment() -> Result<()> {
+        let dir = env::temp_dir().join("waymarks_sparse_track_test");
+        if dir.exists() {
+            fs::remove_dir_all(&dir)?;
+        }
+        fs::create_dir_all(&dir)?;
+        let data_file = dir.join("cities_sample.txt");
+        fs::copy(
+            PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data/cities_sample.txt"),
+            &data_file,
+        )?;
+
+        // two vertices 8 km apart, El Tarter halfway between them
+        let gpx = dir.join("sparse.gpx");
+        fs::write(
+            &gpx,
+            r#"<gpx><trk><trkseg>
+                <trkpt lat="42.5795" lon="1.60"/>
+                <trkpt lat="42.5795" lon="1.70"/>
+            </trkseg></trk></gpx>"#,
+        )?;
+        let track = read_track(&gpx, 0.25)?;
+        assert!(track.points.len() > 30);
+        assert_eq!(track.points.first(), Some(&(42.5795, 1.60)));
+        assert_eq!(track.points.last(), Some(&(42.5795, 1.70)));
+
+        let index = SearchIndex::open_or_build(&data_file)?;
+        let found = index.near_track(&track.points, 0.5, PlaceKind::Populated)?;
+        assert!(found.iter().any(|(place, _)| place.name == "El Tarter"));
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,761 @@
+//! On-disk name index over a GeoNames dump.
+//!
+//! The index lives in a directory next to the dump (`cities500.zip` →
+//! `cities500.idx/`). It is built by streaming the dump, which may stay
+//! zipped, and keeps the rows of populated places (feature class P), spots
+//! such as huts (S) and terrain (T) in `records.gz` for random access; other
+//! classes are never looked up. The rows are compressed in blocks of about
+//! 64 KiB, each a gzip member of its own, so reading a row only inflates its
+//! block and no plain copy of the dump is kept. A row is referred to by a
+//! record offset: where its block starts in `records.gz`, shifted left by
+//! 20 bits, plus where the row starts in the inflated block.
+//!
+//! Names are listed in one partition per country, each line being
+//! `normalized name \t record offset`, with one line per distinct name,
+//! asciiname and alternate name of a row. A lookup only reads the partition of
+//! the requested country and then inflates the blocks of the matching rows.
+//!
+//! Populated places, mountain huts and summits are additionally listed by
+//! one-degree latitude band (`lat42.tsv`, lines
+//! `lat \t lon \t P|S|T \t population \t record offset`) for searches by
+//! position.
+
+use crate::geo::haversine_km;
+use crate::geonames::{self, AlternateNames, Bounds, FeatureClass, Geoname, RecordFilter, Row};
+use crate::text;
+use anyhow::{Context, Result};
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs::{self, File};
+use std::hash::Hash;
+use std::io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+const SOURCE_FILE: &str = "source";
+const RECORDS_FILE: &str = "records.gz";
+/// Bumped whenever the key format changes, so old indexes get rebuilt.
+const FORMAT_VERSION: u32 = 8;
+/// Rows are compressed together until a block holds this many bytes.
+const BLOCK_SIZE: usize = 64 * 1024;
+/// Bits of a record offset holding the row's position in its block.
+const BLOCK_BITS: u32 = 20;
+const KM_PER_DEGREE_LAT: f64 = 111.19;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaceKind {
+    /// Feature class P: cities, towns, villages, ..., and mountain huts, see
+    /// [`Geoname::is_locality`].
+    Populated,
+    /// Mountains, peaks and volcanoes, see [`Geoname::is_summit`].
+    Summit,
+}
+
+struct BandEntry {
+    lat: f64,
+    lon: f64,
+    kind: PlaceKind,
+    population: u64,
+    offset: u64,
+}
+
+pub struct SearchIndex {
+    data_file: PathBuf,
+    dir: PathBuf,
+}
+
+impl SearchIndex {
+    /// Opens the index of `data_file`, building it first if it is missing or was
+    /// built from a different version of the file. The file can be anything
+    /// [`geonames::open_dump`] reads.
+    pub fn open_or_build(data_file: &Path) -> Result<Self> {
+        let index = Self {
+            data_file: data_file.to_path_buf(),
+            dir: index_dir(data_file),
+        };
+
+        let stamp = source_stamp(data_file)?;
+        let current = fs::read_to_string(index.dir.join(SOURCE_FILE)).ok();
+        if current.as_deref() != Some(stamp.as_str()) {
+            eprintln!("Building search index for {}...", data_file.display());
+            index.build(&stamp)?;
+        }
+        Ok(index)
+    }
+
+    /// Returns every record of the given country matching one of `names`, keyed
+    /// like [`geonames::match_names`].
+    pub fn lookup(
+        &self,
+        country_iso: &str,
+        names: &[String],
+    ) -> Result<HashMap<String, Vec<Geoname>>> {
+        let keys: HashSet<String> = names.iter().map(|n| geonames::normalize_name(n)).collect();
+
+        let mut offsets = BTreeSet::new();
+        let partition = self.dir.join(partition_name(country_iso));
+        if partition.exists() {
+            for line in BufReader::new(File::open(&partition)?).lines() {
+                let line = line?;
+                if let Some((key, offset)) = line.split_once('\t')
+                    && keys.contains(key)
+                {
+                    offsets.insert(offset.parse::<u64>()?);
+                }
+            }
+        }
+
+        let records = self.read_records(offsets, AlternateNames::Keep)?;
+        Ok(geonames::match_names(records, names))
+    }
+
+    /// Returns up to `limit` records of the country whose names are close to
+    /// `name`, best first. Used when a name has no exact match.
+    pub fn suggest<F>(
+        &self,
+        country_iso: &str,
+        name: &str,
+        limit: usize,
+        filter: F,
+    ) -> Result<Vec<Geoname>>
+    where
+        F: Fn(&Geoname) -> bool,
+    {
+        let query = geonames::normalize_name(name);
+        let query_len = query.chars().count();
+        let max_distance = (query_len / 3).max(1);
+
+        let mut distances: HashMap<u64, usize> = HashMap::new();
+        let partition = self.dir.join(partition_name(country_iso));
+        if partition.exists() {
+            for line in BufReader::new(File::open(&partition)?).lines() {
+                let line = line?;
+                let Some((key, offset)) = line.split_once('\t') else {
+                    continue;
+                };
+
+                // a key that merely starts like the query is close too, e.g.
+                // "sant julia" for "sant julia de loria"
+                let prefix: String = key.chars().take(query_len).collect();
+                let distance =
+                    text::levenshtein(&query, key).min(text::levenshtein(&query, &prefix) + 1);
+                if distance <= max_distance {
+                    let best = distances.entry(offset.parse()?).or_insert(distance);
+                    *best = (*best).min(distance);
+                }
+            }
+        }
+
+        let distances: Vec<(u64, usize)> = distances.into_iter().collect();
+        let records = self.read_records(
+            distances.iter().map(|(offset, _)| *offset),
+            AlternateNames::Skip,
+        )?;
+        let mut suggestions: Vec<(usize, Geoname)> = distances
+            .into_iter()
+            .map(|(_, distance)| distance)
+            .zip(records)
+            .filter(|(_, record)| filter(record))
+            .collect();
+        suggestions.sort_by(|(da, a), (db, b)| {
+            da.cmp(db).then_with(|| {
+                b.population
+                    .unwrap_or(0.0)
+                    .total_cmp(&a.population.unwrap_or(0.0))
+            })
+        });
+
+        Ok(suggestions
+            .into_iter()
+            .take(limit)
+            .map(|(_, record)| record)
+            .collect())
+    }
+
+    /// Finds the populated place closest to a point, optionally within `max_km`
+    /// and with at least `min_population` inhabitants. Returns it with its
+    /// distance in km.
+    pub fn nearest(
+        &self,
+        lat: f64,
+        lon: f64,
+        max_km: Option<f64>,
+        min_population: u64,
+    ) -> Result<Option<(Geoname, f64)>> {
+        let home = lat_band(lat);
+        let mut best: Option<(u64, f64)> = None;
+
+        // scan latitude bands outwards until no unseen band can hold anything closer
+        for step in 0..=180 {
+            let limit = match (best, max_km) {
+                (Some((_, distance)), _) => distance,
+                (None, Some(max_km)) => max_km,
+                (None, None) => f64::INFINITY,
+            };
+            let band_distance = (step as f64 - 1.0).max(0.0) * KM_PER_DEGREE_LAT;
+            if band_distance > limit {
+                break;
+            }
+
+            let mut bands = vec![home - step];
+            if step > 0 {
+                bands.push(home + step);
+            }
+            for band in bands {
+                for entry in self.read_band(band)? {
+                    if entry.kind != PlaceKind::Populated || entry.population < min_population {
+                        continue;
+                    }
+                    let distance = haversine_km(lat, lon, entry.lat, entry.lon);
+                    if max_km.is_some_and(|max_km| distance > max_km) {
+                        continue;
+                    }
+                    if best.is_none_or(|(_, best_distance)| distance < best_distance) {
+                        best = Some((entry.offset, distance));
+                    }
+                }
+            }
+        }
+
+        let Some((offset, distance)) = best else {
+            return Ok(None);
+        };
+        let record = self.read_records([offset], AlternateNames::Skip)?.pop();
+        Ok(record.map(|record| (record, distance)))
+    }
+
+    /// Finds every place of `kind` within `radius_km` of any point of a track.
+    /// Returns them with their distance to the closest track point, nearest first.
+    pub fn near_track(
+        &self,
+        track: &[(f64, f64)],
+        radius_km: f64,
+        kind: PlaceKind,
+    ) -> Result<Vec<(Geoname, f64)>> {
+        if track.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let bounds = track_bounds(track, radius_km);
+        let mut found: Vec<(u64, f64)> = Vec::new();
+        for band in lat_band(bounds.min_lat)..=lat_band(bounds.max_lat) {
+            for entry in self.read_band(band)? {
+                if entry.kind != kind || !bounds.contains(entry.lat, entry.lon) {
+                    continue;
+                }
+                let distance = track
+                    .iter()
+                    .map(|&(lat, lon)| haversine_km(lat, lon, entry.lat, entry.lon))
+                    .fold(f64::INFINITY, f64::min);
+                if distance <= radius_km {
+                    found.push((entry.offset, distance));
+                }
+            }
+        }
+        found.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+        let records = self.read_records(
+            found.iter().map(|(offset, _)| *offset),
+            AlternateNames::Skip,
+        )?;
+        Ok(records
+            .into_iter()
+            .zip(found.into_iter().map(|(_, distance)| distance))
+            .collect())
+    }
+
+    fn read_band(&self, band: i32) -> Result<Vec<BandEntry>> {
+        let path = self.dir.join(band_name(band));
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut entries = Vec::new();
+        for line in BufReader::new(File::open(&path)?).lines() {
+            let line = line?;
+            let fields: Vec<&str> = line.split('\t').collect();
+            let [lat, lon, kind, population, offset] = fields[..] else {
+                continue;
+            };
+            let kind = match kind {
+                "P" | "S" => PlaceKind::Populated,
+                "T" => PlaceKind::Summit,
+                _ => continue,
+            };
+            entries.push(BandEntry {
+                lat: lat.parse()?,
+                lon: lon.parse()?,
+                kind,
+                population: population.parse().unwrap_or(0),
+                offset: offset.parse()?,
+            });
+        }
+        Ok(entries)
+    }
+
+    /// Reads the rows at `offsets`, in that order. Rows are read block by
+    /// block, so each block is inflated once however many rows it holds.
+    fn read_records<I>(&self, offsets: I, alternate_names: AlternateNames) -> Result<Vec<Geoname>>
+    where
+        I: IntoIterator<Item = u64>,
+    {
+        let mut wanted: Vec<(u64, usize)> = offsets
+            .into_iter()
+            .enumerate()
+            .map(|(i, offset)| (offset, i))
+            .collect();
+        wanted.sort();
+
+        let mut file = File::open(self.dir.join(RECORDS_FILE))?;
+        let mut block: Option<(u64, Vec<u8>)> = None;
+        let mut records: Vec<Option<Geoname>> = (0..wanted.len()).map(|_| None).collect();
+        for (offset, i) in wanted {
+            let start = offset >> BLOCK_BITS;
+            let within = (offset & ((1 << BLOCK_BITS) - 1)) as usize;
+            if block.as_ref().is_none_or(|(current, _)| *current != start) {
+                file.seek(SeekFrom::Start(start))?;
+                let mut data = Vec::new();
+                GzDecoder::new(BufReader::new(&mut file)).read_to_end(&mut data)?;
+                block = Some((start, data));
+            }
+            let (_, data) = block.as_ref().unwrap();
+            let line = data
+                .get(within..)
+                .and_then(|rest| rest.split(|b| *b == b'\n').next())
+                .with_context(|| format!("Invalid record offset {offset}"))?;
+            let row = Row::new(String::from_utf8(line.to_vec())?);
+            records[i] = Some(row.to_geoname(alternate_names)?);
+        }
+        Ok(records.into_iter().flatten().collect())
+    }
+
+    fn build(&self, stamp: &str) -> Result<()> {
+        // build next to the final location and swap it in, so an interrupted run
+        // never leaves a partial index behind
+        let tmp_dir = self.dir.with_extension("idx.tmp");
+        if tmp_dir.exists() {
+            fs::remove_dir_all(&tmp_dir)?;
+        }
+        fs::create_dir_all(&tmp_dir)
+            .with_context(|| format!("Failed to create directory {}", tmp_dir.display()))?;
+
+        let mut partitions: HashMap<String, BufWriter<File>> = HashMap::new();
+        let mut bands: HashMap<i32, BufWriter<File>> = HashMap::new();
+        let mut records = BlockWriter::new(File::create(tmp_dir.join(RECORDS_FILE))?);
+        let filter = RecordFilter {
+            feature_classes: vec![FeatureClass::P, FeatureClass::S, FeatureClass::T],
+        };
+        for row in geonames::rows(geonames::open_dump(&self.data_file)?, &filter) {
+            let row = row?;
+            let offset = records.push(row.line())?;
+
+            let columns = row.columns();
+            if let [
+                _,
+                name,
+                asciiname,
+                alternatenames,
+                lat,
+                lon,
+                class,
+                code,
+                country,
+                ..,
+            ] = columns[..]
+                && !country.is_empty()
+            {
+                let writer = partition_writer(&mut partitions, country.to_string(), || {
+                    tmp_dir.join(partition_name(country))
+                })?;
+
+                let mut keys: Vec<String> = [name, asciiname]
+                    .into_iter()
+                    .chain(alternatenames.split(','))
+                    .map(geonames::normalize_name)
+                    .filter(|k| !k.is_empty())
+                    .collect();
+                keys.sort();
+                keys.dedup();
+                for key in keys {
+                    writeln!(writer, "{key}\t{offset}")?;
+                }
+
+                let kind = match class {
+                    "P" => Some("P"),
+                    "S" if geonames::HUT_FEATURE_CODES.contains(&code) => Some("S"),
+                    "T" if geonames::SUMMIT_FEATURE_CODES.contains(&code) => Some("T"),
+                    _ => None,
+                };
+                if let Some(kind) = kind
+                    && let Ok(lat_value) = lat.parse::<f64>()
+                {
+                    let band = lat_band(lat_value);
+                    let population = columns.get(14).copied().unwrap_or_default();
+                    let writer =
+                        partition_writer(&mut bands, band, || tmp_dir.join(band_name(band)))?;
+                    writeln!(writer, "{lat}\t{lon}\t{kind}\t{population}\t{offset}")?;
+                }
+            }
+        }
+
+        records.finish()?;
+        for (_, mut writer) in partitions {
+            writer.flush()?;
+        }
+        for (_, mut writer) in bands {
+            writer.flush()?;
+        }
+        fs::write(tmp_dir.join(SOURCE_FILE), stamp)?;
+
+        remove_index(&self.data_file)?;
+        fs::rename(&tmp_dir, &self.dir)?;
+        Ok(())
+    }
+}
+
+/// Rough bounding box of a track grown by `radius_km`, to skip most places
+/// before computing distances. A track crossing the ±180° meridian gets a box
+/// crossing it too, see [`Bounds`].
+fn track_bounds(track: &[(f64, f64)], radius_km: f64) -> Bounds {
+    let margin_lat = radius_km / KM_PER_DEGREE_LAT;
+    let min_lat = track.iter().map(|p| p.0).fold(f64::INFINITY, f64::min) - margin_lat;
+    let max_lat = track.iter().map(|p| p.0).fold(f64::NEG_INFINITY, f64::max) + margin_lat;
+    let widest = min_lat
+        .abs()
+        .max(max_lat.abs())
+        .min(89.0)
+        .to_radians()
+        .cos();
+    let margin_lon = radius_km / (KM_PER_DEGREE_LAT * widest);
+
+    // the narrower of the spans measured from -180° and from 0°
+    let span = |shift: f64| {
+        let lons = track
+            .iter()
+            .map(|p| (p.1 + shift).rem_euclid(360.0) - shift);
+        let min = lons.clone().fold(f64::INFINITY, f64::min);
+        let max = lons.fold(f64::NEG_INFINITY, f64::max);
+        (min, max)
+    };
+    let (west, east) = [span(180.0), span(0.0)]
+        .into_iter()
+        .min_by(|a, b| (a.1 - a.0).total_cmp(&(b.1 - b.0)))
+        .unwrap();
+    let (min_lon, max_lon) = if east - west + 2.0 * margin_lon >= 360.0 {
+        (-180.0, 180.0)
+    } else {
+        let wrap = |lon: f64| (lon + 180.0).rem_euclid(360.0) - 180.0;
+        (wrap(west - margin_lon), wrap(east + margin_lon))
+    };
+    Bounds {
+        min_lat,
+        max_lat,
+        min_lon,
+        max_lon,
+    }
+}
+
+/// Writes rows to `records.gz`, see the module documentation.
+struct BlockWriter {
+    file: BufWriter<File>,
+    written: u64,
+    block: Vec<u8>,
+}
+
+impl BlockWriter {
+    fn new(file: File) -> Self {
+        Self {
+            file: BufWriter::new(file),
+            written: 0,
+            block: Vec::with_capacity(BLOCK_SIZE * 2),
+        }
+    }
+
+    /// Adds a row, returning its record offset.
+    fn push(&mut self, line: &str) -> Result<u64> {
+        if self.block.len() >= BLOCK_SIZE {
+            self.write_block()?;
+        }
+        let offset = (self.written << BLOCK_BITS) | self.block.len() as u64;
+        self.block.extend_from_slice(line.as_bytes());
+        self.block.push(b'\n');
+        Ok(offset)
+    }
+
+    fn write_block(&mut self) -> Result<()> {
+        if self.block.is_empty() {
+            return Ok(());
+        }
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&self.block)?;
+        let compressed = encoder.finish()?;
+        self.file.write_all(&compressed)?;
+        self.written += compressed.len() as u64;
+        self.block.clear();
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<()> {
+        self.write_block()?;
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+/// Deletes the index of `data_file`, if any. Call after the dump is replaced.
+pub fn remove_index(data_file: &Path) -> Result<()> {
+    let dir = index_dir(data_file);
+    if dir.exists() {
+        fs::remove_dir_all(&dir)
+            .with_context(|| format!("Failed to remove index {}", dir.display()))?;
+    }
+    Ok(())
+}
+
+fn index_dir(data_file: &Path) -> PathBuf {
+    data_file.with_extension("idx")
+}
+
+fn partition_name(country_iso: &str) -> String {
+    format!("{}.tsv", country_iso.to_uppercase())
+}
+
+fn band_name(band: i32) -> String {
+    format!("lat{band}.tsv")
+}
+
+/// Populated places are also partitioned into one-degree latitude bands.
+fn lat_band(lat: f64) -> i32 {
+    lat.floor() as i32
+}
+
+fn partition_writer<K, F>(
+    writers: &mut HashMap<K, BufWriter<File>>,
+    key: K,
+    path: F,
+) -> Result<&mut BufWriter<File>>
+where
+    K: Eq + Hash,
+    F: FnOnce() -> PathBuf,
+{
+    Ok(match writers.entry(key) {
+        Entry::Occupied(entry) => entry.into_mut(),
+        Entry::Vacant(entry) => entry.insert(BufWriter::new(File::create(path())?)),
+    })
+}
+
+/// Identifies the version of the dump an index was built from.
+fn source_stamp(data_file: &Path) -> Result<String> {
+    let metadata = fs::metadata(data_file)
+        .with_context(|| format!("Failed to read {}", data_file.display()))?;
+    let modified = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    Ok(format!("v{FORMAT_VERSION} {} {modified}", metadata.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_build_and_lookup() -> Result<()> {
+        let dir = env::temp_dir().join("waymarks_index_test");
+        if dir.exists() {
+            fs::remove_dir_all(&dir)?;
+        }
+        fs::create_dir_all(&dir)?;
+
+        let data_file = dir.join("cities_sample.txt");
+        fs::copy(
+            PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data/cities_sample.txt"),
+            &data_file,
+        )?;
+
+        let index = SearchIndex::open_or_build(&data_file)?;
+        assert!(dir.join("cities_sample.idx/AD.tsv").exists());
+
+        let names = vec!["Sant Julia de Loria".to_string(), "soldeu".to_string()];
+        let found = index.lookup("ad", &names)?;
+        assert_eq!(found["sant julia de loria"][0].geonameid, 3039163);
+        assert_eq!(found["soldeu"][0].name, "Soldeu");
+
+        let expected: Vec<Geoname> = geonames::read_tsv(&data_file)?;
+        assert_eq!(found["soldeu"][0], expected[1]);
+
+        assert!(index.lookup("FR", &names)?.values().all(Vec::is_empty));
+
+        let found = index.lookup("AD", &["Ehl Tarter".to_string()])?;
+        assert_eq!(found["ehl tarter"][0].name, "El Tarter");
+
+        let suggestions = index.suggest("AD", "Soldue", 5, |_| true)?;
+        assert_eq!(suggestions.len(), 1);
+        assert_eq!(suggestions[0].name, "Soldeu");
+
+        let suggestions = index.suggest("AD", "Sant Julia", 5, |_| true)?;
+        assert_eq!(suggestions[0].name, "Sant Julià de Lòria");
+        assert!(index.suggest("AD", "Sant Julia", 5, |_| false)?.is_empty());
+
+        let (nearest, distance) = index.nearest(42.58, 1.66, None, 0)?.unwrap();
+        assert_eq!(nearest.name, "El Tarter");
+        assert!(distance < 1.0);
+
+        let (nearest, _) = index.nearest(42.58, 1.66, Some(20.0), 2000)?.unwrap();
+        assert_eq!(nearest.name, "Pas de la Casa");
+        assert!(index.nearest(42.58, 1.66, Some(5.0), 2000)?.is_none());
+        assert!(index.nearest(-33.9, 18.4, Some(100.0), 0)?.is_none());
+
+        let track = [(42.5766, 1.6672), (42.5795, 1.6536)];
+        let found = index.near_track(&track, 0.5, PlaceKind::Populated)?;
+        let found: Vec<&str> = found.iter().map(|(g, _)| g.name.as_str()).collect();
+        assert_eq!(found, ["El Tarter", "Soldeu"]);
+        assert!(index.near_track(&track, 0.5, PlaceKind::Summit)?.is_empty());
+
+        // without a radius the search reaches across bands
+        let (nearest, distance) = index.nearest(45.0, 1.66, None, 0)?.unwrap();
+        assert_eq!(nearest.name, "El Tarter");
+        assert!(distance > 200.0);
+
+        // reopening an up to date index does not rebuild it
+        fs::write(dir.join("cities_sample.idx/AD.tsv"), "")?;
+        let index = SearchIndex::open_or_build(&data_file)?;
+        assert!(index.lookup("AD", &names)?.values().all(Vec::is_empty));
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_country_dump_huts() -> Result<()> {
+        let dir = env::temp_dir().join("waymarks_index_huts_test");
+        if dir.exists() {
+            fs::remove_dir_all(&dir)?;
+        }
+        fs::create_dir_all(&dir)?;
+
+        // a per-country dump lists huts and other spots next to the villages
+        let mut dump = fs::read_to_string(
+            PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data/cities_sample.txt"),
+        )?;
+        dump.push_str("9000001\tRefugi de Juclar\tRefugi de Juclar\t\t42.60810\t1.72280\tS\tHUT\tAD\t\t02\t\t\t\t0\t2294\t2290\tEurope/Andorra\t2020-01-01\n");
+        dump.push_str("9000002\tHotel Juclar\tHotel Juclar\t\t42.60820\t1.72290\tS\tHTL\tAD\t\t02\t\t\t\t0\t\t2290\tEurope/Andorra\t2020-01-01\n");
+        let data_file = dir.join("AD.txt");
+        fs::write(&data_file, dump)?;
+        let index = SearchIndex::open_or_build(&data_file)?;
+
+        let found = index.lookup("AD", &["Refugi de Juclar".to_string()])?;
+        let hut = &found["refugi de juclar"][0];
+        assert!(hut.is_locality());
+        let found = index.lookup("AD", &["Hotel Juclar".to_string()])?;
+        assert!(!found["hotel juclar"][0].is_locality());
+
+        let (nearest, distance) = index.nearest(42.6085, 1.7230, None, 0)?.unwrap();
+        assert_eq!(nearest.name, "Refugi de Juclar");
+        assert!(distance < 0.1);
+
+        let track = [(42.6070, 1.7210), (42.6090, 1.7240)];
+        let found = index.near_track(&track, 0.5, PlaceKind::Populated)?;
+        let names: Vec<&str> = found.iter().map(|(g, _)| g.name.as_str()).collect();
+        assert_eq!(names, ["Refugi de Juclar"]);
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_near_track_at_dateline() -> Result<()> {
+        let dir = env::temp_dir().join("waymarks_index_dateline_test");
+        if dir.exists() {
+            fs::remove_dir_all(&dir)?;
+        }
+        fs::create_dir_all(&dir)?;
+
+        // Taveuni, Fiji, lies on both sides of the ±180° meridian
+        let place = |id: u32, name: &str, lon: f64| {
+            format!(
+                "{id}\t{name}\t{name}\t\t-16.80000\t{lon:.5}\tP\tPPL\tFJ\t\t03\t\t\t\t100\t\t20\tPacific/Fiji\t2020-01-01\n"
+            )
+        };
+        let dump = [
+            place(1, "West", 179.99),
+            place(2, "East", -179.99),
+            place(3, "Far", -179.0),
+        ]
+        .concat();
+        let data_file = dir.join("FJ.txt");
+        fs::write(&data_file, dump)?;
+        let index = SearchIndex::open_or_build(&data_file)?;
+
+        let names = |track: &[(f64, f64)]| -> Result<Vec<String>> {
+            let found = index.near_track(track, 5.0, PlaceKind::Populated)?;
+            Ok(found.into_iter().map(|(g, _)| g.name).collect())
+        };
+        assert_eq!(names(&[(-16.8, 179.98)])?, ["West", "East"]);
+        assert_eq!(names(&[(-16.8, -179.98)])?, ["East", "West"]);
+        assert_eq!(
+            names(&[(-16.8, 179.995), (-16.8, -179.995)])?,
+            ["West", "East"]
+        );
+
+        // a track across the meridian keeps a narrow box
+        let bounds = track_bounds(&[(-16.8, 179.995), (-16.8, -179.995)], 5.0);
+        assert!(bounds.min_lon > 179.9 && bounds.max_lon < -179.9);
+        let bounds = track_bounds(&[(42.5, 1.5), (42.6, 1.7)], 5.0);
+        assert!(bounds.min_lon > 1.4 && bounds.max_lon < 1.8);
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_records_in_blocks() -> Result<()> {
+        let dir = env::temp_dir().join("waymarks_index_blocks_test");
+        if dir.exists() {
+            fs::remove_dir_all(&dir)?;
+        }
+        fs::create_dir_all(&dir)?;
+
+        // enough rows for several blocks
+        let mut dump = String::new();
+        for id in 0..3000 {
+            dump.push_str(&format!(
+                "{id}\tPlace {id}\tPlace {id}\t\t42.5\t1.5\tP\tPPL\tAD\t\t02\t\t\t\t{id}\t\t1000\tEurope/Andorra\t2020-01-01\n"
+            ));
+        }
+        let data_file = dir.join("blocks.txt");
+        fs::write(&data_file, &dump)?;
+
+        let index = SearchIndex::open_or_build(&data_file)?;
+        let records = dir.join("blocks.idx").join(RECORDS_FILE);
+        assert!(fs::metadata(&records)?.len() < dump.len() as u64 / 4);
+
+        let names: Vec<String> = ["Place 2999", "Place 0", "Place 1500"]
+            .map(String::from)
+            .to_vec();
+        let found = index.lookup("AD", &names)?;
+        assert_eq!(found["place 2999"][0].geonameid, 2999);
+        assert_eq!(found["place 0"][0].geonameid, 0);
+        assert_eq!(found["place 1500"][0].population, Some(1500.0));
+
+        // rows come back in the order asked for, whatever their blocks
+        let offsets: Vec<u64> = fs::read_to_string(dir.join("blocks.idx/AD.tsv"))?
+            .lines()
+            .filter_map(|line| line.split_once('\t')?.1.parse().ok())
+            .collect();
+        assert!(offsets.iter().any(|offset| offset >> BLOCK_BITS > 0));
+        let reversed: Vec<u64> = offsets.iter().rev().copied().collect();
+        let read = index.read_records(reversed, AlternateNames::Skip)?;
+        assert_eq!(read.len(), 3000);
+        assert_eq!(read[0].geonameid, 2999);
+        assert_eq!(read[2999].geonameid, 0);
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
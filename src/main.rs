@@ -1,16 +1,34 @@
+use std::path::PathBuf;
 use std::time::Instant;
 
 use config::Config;
 
 mod commands;
 mod config;
+mod deltas;
 mod file_ops;
+mod geo;
 mod geonames;
+mod gpx;
+mod index;
 mod models;
+mod output;
+mod prompt;
+mod strava;
+mod text;
 
-use crate::commands::cities;
+use crate::commands::list::{self, Kind, Query, SortKey};
+use crate::commands::places::Selection;
+use crate::commands::tracks::{self, TrackOptions};
+use crate::commands::{
+    cities, countries, data, migrate, stats, strava as strava_commands, summits,
+};
+use crate::geonames::Continent;
+use crate::models::cities::{Coordinates, Visit};
+use crate::output::OutputFormat;
 use anyhow::Result;
-use clap::{Parser, Subcommand};
+use chrono::NaiveDate;
+use clap::{Args, Parser, Subcommand};
 use colored::*;
 
 #[derive(Parser)]
@@ -26,7 +44,263 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
     #[command(alias = "ac")]
-    AddCities { country: String, names: Vec<String> },
+    AddCities {
+        country: String,
+        names: Vec<String>,
+        /// Only consider places in this GeoNames admin1 code (e.g. "60" for Valencia)
+        #[arg(long)]
+        admin1: Option<String>,
+        /// Prefer the place closest to `lat,lon`
+        #[arg(long, allow_hyphen_values = true)]
+        near: Option<Coordinates>,
+        /// Ask which place is meant when a name is ambiguous
+        #[arg(short, long)]
+        interactive: bool,
+        #[command(flatten)]
+        visit: VisitArgs,
+    },
+    /// Add the populated place nearest to a point
+    #[command(allow_negative_numbers = true)]
+    AddAt {
+        lat: f64,
+        lon: f64,
+        /// Only consider places within this many km
+        #[arg(long)]
+        max_distance: Option<f64>,
+        /// Only consider places with at least this many inhabitants
+        #[arg(long, default_value_t = 0)]
+        min_population: u64,
+        #[command(flatten)]
+        visit: VisitArgs,
+    },
+    #[command(alias = "rc")]
+    RemoveCities { country: String, names: Vec<String> },
+    /// Add countries visited without recording a city, e.g. crossed in transit
+    AddCountries {
+        /// Names, aliases or ISO codes
+        #[arg(required = true)]
+        names: Vec<String>,
+        #[command(flatten)]
+        visit: VisitArgs,
+    },
+    /// Remove countries, together with their cities after confirmation
+    RemoveCountries {
+        #[arg(required = true)]
+        names: Vec<String>,
+    },
+    #[command(alias = "as")]
+    AddSummits {
+        country: String,
+        names: Vec<String>,
+        /// Only consider peaks in this GeoNames admin1 code
+        #[arg(long)]
+        admin1: Option<String>,
+        /// Prefer the peak closest to `lat,lon`
+        #[arg(long, allow_hyphen_values = true)]
+        near: Option<Coordinates>,
+        /// Ask which peak is meant when a name is ambiguous
+        #[arg(short, long)]
+        interactive: bool,
+        /// Day the summit was reached (YYYY-MM-DD)
+        #[arg(long)]
+        date: Option<NaiveDate>,
+    },
+    /// Record the cities and summits along a GPX track
+    ImportGpx {
+        file: PathBuf,
+        #[command(flatten)]
+        track: TrackArgs,
+    },
+    /// Download GeoNames files again where the server has newer ones
+    UpdateData {
+        /// Apply the daily modification and deletion files to the cities dump
+        /// instead of downloading it again
+        #[arg(long)]
+        daily: bool,
+        /// First day to apply the daily files of, by default the day after
+        /// the last applied one
+        #[arg(long, requires = "daily")]
+        since: Option<NaiveDate>,
+    },
+    /// Show how many countries and cities were visited, by continent
+    Stats {
+        #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+        format: OutputFormat,
+        /// Number of countries listed by city count
+        #[arg(long, default_value_t = 10)]
+        top: usize,
+    },
+    /// List the recorded countries, cities or summits
+    List {
+        #[command(subcommand)]
+        what: ListCommand,
+    },
+    /// Find recorded countries, cities and summits by name
+    Search {
+        text: String,
+        #[command(flatten)]
+        query: QueryArgs,
+    },
+    /// Key countries.json and the city files by ISO code instead of by name,
+    /// merging names of the same country
+    Migrate {
+        /// Country a name that does not resolve on its own stands for
+        /// (`name=ISO`, e.g. `turkey=TR`), repeatable
+        #[arg(long = "alias", value_parser = parse_alias)]
+        aliases: Vec<(String, String)>,
+        /// Only list what would be merged
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Allow access to your Strava activities
+    StravaLogin,
+    /// Record the cities and summits along new Strava activities
+    StravaSync {
+        /// Sync activities started on or after this day instead of the new ones
+        #[arg(long)]
+        after: Option<NaiveDate>,
+        #[command(flatten)]
+        track: TrackArgs,
+    },
+}
+
+#[derive(Subcommand)]
+enum ListCommand {
+    Countries {
+        #[command(flatten)]
+        query: QueryArgs,
+    },
+    Cities {
+        /// Only cities in this country, same as --country
+        #[arg(value_name = "COUNTRY", conflicts_with = "country")]
+        in_country: Option<String>,
+        #[command(flatten)]
+        query: QueryArgs,
+    },
+    Summits {
+        #[command(flatten)]
+        query: QueryArgs,
+    },
+}
+
+#[derive(Args)]
+struct QueryArgs {
+    /// Only places in this country, by name, alias or ISO code
+    #[arg(long)]
+    country: Option<String>,
+    /// Only places on this continent, by code (EU) or name
+    #[arg(long)]
+    continent: Option<Continent>,
+    /// Only places visited on or after this day
+    #[arg(long)]
+    since: Option<NaiveDate>,
+    /// Only places visited on or before this day
+    #[arg(long)]
+    until: Option<NaiveDate>,
+    #[arg(long, value_enum, default_value_t = SortKey::Name)]
+    sort: SortKey,
+    /// Reverse the order, places without the sorted value staying last
+    #[arg(long)]
+    reverse: bool,
+    #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+    format: OutputFormat,
+}
+
+impl QueryArgs {
+    fn into_query(self) -> Result<Query> {
+        if let (Some(since), Some(until)) = (self.since, self.until)
+            && until < since
+        {
+            anyhow::bail!("--until must not be before --since");
+        }
+        Ok(Query {
+            country: self.country,
+            continent: self.continent,
+            since: self.since,
+            until: self.until,
+            sort: self.sort,
+            reverse: self.reverse,
+            format: self.format,
+        })
+    }
+}
+
+#[derive(Args)]
+struct TrackArgs {
+    /// Populated places closer than this many km to the track count as visited
+    #[arg(long, default_value_t = 1.0)]
+    city_radius: f64,
+    /// Peaks closer than this many km to the track count as reached
+    #[arg(long, default_value_t = 0.2)]
+    summit_radius: f64,
+    /// Ignore places with fewer inhabitants
+    #[arg(long, default_value_t = 0)]
+    min_population: u64,
+    /// Only list what would be recorded
+    #[arg(long)]
+    dry_run: bool,
+    /// Record without asking for confirmation
+    #[arg(short, long)]
+    yes: bool,
+}
+
+impl TrackArgs {
+    fn into_options(self) -> Result<TrackOptions> {
+        if self.city_radius <= 0.0 || self.summit_radius <= 0.0 {
+            anyhow::bail!("--city-radius and --summit-radius must be positive");
+        }
+        Ok(TrackOptions {
+            city_radius_km: self.city_radius,
+            summit_radius_km: self.summit_radius,
+            min_population: self.min_population,
+            dry_run: self.dry_run,
+            yes: self.yes,
+        })
+    }
+}
+
+#[derive(Args)]
+struct VisitArgs {
+    /// Record a visit on this day (YYYY-MM-DD)
+    #[arg(long, conflicts_with_all = ["from", "to"])]
+    date: Option<NaiveDate>,
+    /// Record a visit starting on this day
+    #[arg(long)]
+    from: Option<NaiveDate>,
+    /// Last day of the visit started with --from
+    #[arg(long, requires = "from")]
+    to: Option<NaiveDate>,
+    /// Free-text note stored with the visit
+    #[arg(long)]
+    note: Option<String>,
+}
+
+impl VisitArgs {
+    fn into_visit(self) -> Result<Option<Visit>> {
+        let Some(from) = self.date.or(self.from) else {
+            if self.note.is_some() {
+                anyhow::bail!("--note needs a visit date, pass --date or --from");
+            }
+            return Ok(None);
+        };
+        if self.to.is_some_and(|to| to < from) {
+            anyhow::bail!("--to must not be before --from");
+        }
+        Ok(Some(Visit {
+            from,
+            to: self.to,
+            note: self.note,
+        }))
+    }
+}
+
+fn parse_alias(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((name, iso)) if !name.is_empty() && iso.len() == 2 => {
+            Ok((name.to_string(), iso.to_string()))
+        }
+        _ => Err(format!("expected name=ISO, got '{s}'")),
+    }
 }
 
 #[tokio::main]
@@ -44,13 +318,99 @@ async fn run() -> Result<()> {
     let start = Instant::now();
 
     match cli.command {
-        Commands::AddCities { country, names } => {
-            cities::add_cities(&cfg, &country, &names).await?;
+        Commands::AddCities {
+            country,
+            names,
+            admin1,
+            near,
+            interactive,
+            visit,
+        } => {
+            let selection = Selection {
+                admin1,
+                near,
+                interactive,
+            };
+            let visit = visit.into_visit()?;
+            cities::add_cities(&cfg, &country, &names, &selection, visit.as_ref()).await?;
+        }
+        Commands::AddAt {
+            lat,
+            lon,
+            max_distance,
+            min_population,
+            visit,
+        } => {
+            let visit = visit.into_visit()?;
+            cities::add_city_at(&cfg, lat, lon, max_distance, min_population, visit.as_ref())
+                .await?;
+        }
+        Commands::RemoveCities { country, names } => {
+            cities::remove_cities(&cfg, &country, &names).await?;
+        }
+        Commands::AddCountries { names, visit } => {
+            let visit = visit.into_visit()?;
+            countries::add_countries(&cfg, &names, visit.as_ref()).await?;
+        }
+        Commands::RemoveCountries { names } => {
+            countries::remove_countries(&cfg, &names).await?;
+        }
+        Commands::AddSummits {
+            country,
+            names,
+            admin1,
+            near,
+            interactive,
+            date,
+        } => {
+            let selection = Selection {
+                admin1,
+                near,
+                interactive,
+            };
+            summits::add_summits(&cfg, &country, &names, &selection, date).await?;
+        }
+        Commands::ImportGpx { file, track } => {
+            tracks::import_gpx(&cfg, &file, &track.into_options()?).await?;
+        }
+        Commands::UpdateData { daily, since } => {
+            if daily {
+                data::apply_daily_updates(&cfg, since).await?;
+            } else {
+                data::update_data(&cfg).await?;
+            }
+        }
+        Commands::Stats { format, top } => {
+            stats::stats(&cfg, format, top).await?;
+        }
+        Commands::List { what } => {
+            let (kind, query) = match what {
+                ListCommand::Countries { query } => (Kind::Country, query.into_query()?),
+                ListCommand::Cities { in_country, query } => {
+                    let mut query = query.into_query()?;
+                    query.country = in_country.or(query.country);
+                    (Kind::City, query)
+                }
+                ListCommand::Summits { query } => (Kind::Summit, query.into_query()?),
+            };
+            list::list(&cfg, kind, &query).await?;
+        }
+        Commands::Search { text, query } => {
+            list::search(&cfg, &text, &query.into_query()?).await?;
+        }
+        Commands::Migrate { aliases, dry_run } => {
+            migrate::migrate(&cfg, &aliases, dry_run).await?;
+        }
+        Commands::StravaLogin => {
+            strava_commands::login(&cfg).await?;
+        }
+        Commands::StravaSync { after, track } => {
+            strava_commands::sync(&cfg, after, &track.into_options()?).await?;
         }
     }
 
     let duration = start.elapsed();
-    println!("{}", format!("Command finished in {duration:.2?}").blue());
+    eprintln!("{}", format!("Command finished in {duration:.2?}").blue());
 
     Ok(())
 }
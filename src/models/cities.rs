@@ -1,15 +1,103 @@
+use crate::geonames::Geoname;
 use anyhow::Result;
+use chrono::NaiveDate;
 use serde::{Deserialize, Serialize};
-use std::{collections::BTreeMap, fs};
+use std::{
+    collections::{BTreeMap, btree_map::Entry},
+    fmt, fs, mem,
+    str::FromStr,
+};
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Coordinates {
     pub lat: f64,
     pub lon: f64,
 }
 
+impl FromStr for Coordinates {
+    type Err = String;
+
+    /// Parses `lat,lon` in decimal degrees.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (lat, lon) = s
+            .split_once(',')
+            .ok_or_else(|| format!("expected 'lat,lon', got '{s}'"))?;
+        let lat: f64 = lat
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid latitude '{lat}'"))?;
+        let lon: f64 = lon
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid longitude '{lon}'"))?;
+
+        if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) {
+            return Err(format!("coordinates out of range: {lat},{lon}"));
+        }
+        Ok(Self { lat, lon })
+    }
+}
+
+/// A visited city. Files written before GeoNames metadata was kept only contain
+/// `lat` and `lon`, so everything else is optional.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct City {
+    pub lat: f64,
+    pub lon: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub geonameid: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub feature_code: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub admin1_code: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub population: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub visits: Vec<Visit>,
+}
+
+/// A single stay in a city: one day, or a range when `to` is set.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Visit {
+    pub from: NaiveDate,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to: Option<NaiveDate>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+impl fmt::Display for Visit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.from)?;
+        if let Some(to) = self.to {
+            write!(f, " – {to}")?;
+        }
+        if let Some(note) = &self.note {
+            write!(f, " ({note})")?;
+        }
+        Ok(())
+    }
+}
+
+impl From<&Geoname> for City {
+    fn from(geoname: &Geoname) -> Self {
+        Self {
+            lat: geoname.latitude,
+            lon: geoname.longitude,
+            geonameid: Some(geoname.geonameid),
+            feature_code: Some(geoname.feature_code.clone()),
+            admin1_code: geoname.admin1_code.clone(),
+            population: geoname.population.map(|p| p as u64),
+            timezone: geoname.timezone.clone(),
+            visits: Vec::new(),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Cities {
-    pub cities: BTreeMap<String, Coordinates>,
+    pub cities: BTreeMap<String, City>,
 }
 
 impl Cities {
@@ -19,8 +107,87 @@ impl Cities {
         }
     }
 
-    pub fn add(&mut self, name: String, coordinates: Coordinates) -> bool {
-        self.cities.insert(name, coordinates).is_none()
+    /// Adds a city unless the name is already recorded.
+    pub fn add(&mut self, name: String, city: City) -> bool {
+        match self.cities.entry(name) {
+            Entry::Vacant(entry) => {
+                entry.insert(city);
+                true
+            }
+            Entry::Occupied(_) => false,
+        }
+    }
+
+    /// Replaces an entry written before metadata was kept, keeping its visits.
+    /// Entries that already have a geonameid are left alone.
+    pub fn fill_metadata(&mut self, name: &str, city: City) -> bool {
+        match self.cities.get_mut(name) {
+            Some(existing) if existing.geonameid.is_none() => {
+                let visits = mem::take(&mut existing.visits);
+                *existing = City { visits, ..city };
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Records a visit to a stored city, keeping visits in date order.
+    /// Returns false if the city is unknown or the same visit is already recorded.
+    pub fn add_visit(&mut self, name: &str, visit: Visit) -> bool {
+        let Some(city) = self.cities.get_mut(name) else {
+            return false;
+        };
+        if city.visits.contains(&visit) {
+            return false;
+        }
+        city.visits.push(visit);
+        city.visits.sort();
+        true
+    }
+
+    /// Returns the name under which the given GeoNames place is stored.
+    pub fn find_by_geonameid(&self, geonameid: i64) -> Option<&str> {
+        self.cities
+            .iter()
+            .find(|(_, city)| city.geonameid == Some(geonameid))
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Removes a city by name, ignoring case. Returns the stored name on success.
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        let key = self
+            .cities
+            .keys()
+            .find(|k| k.to_lowercase() == name.to_lowercase())?
+            .clone();
+        self.cities.remove(&key);
+        Some(key)
+    }
+
+    /// Adds the cities of another file, e.g. one kept for the same country
+    /// under another name. A place in both, by geonameid or name, keeps one
+    /// entry with the visits of both.
+    pub fn merge(&mut self, other: Cities) {
+        for (name, mut city) in other.cities {
+            let existing = city
+                .geonameid
+                .and_then(|id| self.find_by_geonameid(id))
+                .or_else(|| self.cities.contains_key(&name).then_some(name.as_str()))
+                .map(str::to_string);
+            let Some(existing) = existing else {
+                self.cities.insert(name, city);
+                continue;
+            };
+            let visits = mem::take(&mut city.visits);
+            self.fill_metadata(&existing, city);
+            for visit in visits {
+                self.add_visit(&existing, visit);
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cities.is_empty()
     }
 
     pub fn save_to_file(&self, path: &str) -> Result<()> {
@@ -31,7 +198,7 @@ impl Cities {
 
     pub fn load_from_file(path: &str) -> Result<Self> {
         let content = fs::read_to_string(path)?;
-        let cities: BTreeMap<String, Coordinates> = serde_json::from_str(&content)?;
+        let cities: BTreeMap<String, City> = serde_json::from_str(&content)?;
         Ok(Self { cities })
     }
 }
@@ -41,21 +208,40 @@ mod tests {
 
     use super::*;
 
+    fn city(lat: f64, lon: f64, geonameid: Option<i64>) -> City {
+        City {
+            lat,
+            lon,
+            geonameid,
+            feature_code: None,
+            admin1_code: None,
+            population: None,
+            timezone: None,
+            visits: Vec::new(),
+        }
+    }
+
+    fn date(s: &str) -> NaiveDate {
+        s.parse().unwrap()
+    }
+
     #[test]
     fn test_add_and_save_load() -> Result<()> {
         let mut cities = Cities::new();
-        let berlin = Coordinates {
-            lat: 52.5200,
-            lon: 13.4050,
-        };
-        let munich = Coordinates {
-            lat: 48.13743,
-            lon: 11.57549,
+        let berlin = City {
+            feature_code: Some("PPLC".to_string()),
+            admin1_code: Some("16".to_string()),
+            population: Some(3_426_354),
+            timezone: Some("Europe/Berlin".to_string()),
+            ..city(52.5200, 13.4050, Some(2950159))
         };
+        let munich = city(48.13743, 11.57549, Some(2867714));
 
         assert!(cities.add("Berlin".to_string(), berlin.clone()));
         assert!(cities.add("Munich".to_string(), munich.clone()));
         assert!(!cities.add("Berlin".to_string(), berlin.clone()));
+        assert_eq!(cities.find_by_geonameid(2867714), Some("Munich"));
+        assert_eq!(cities.find_by_geonameid(1), None);
 
         let mut tmp_path = std::env::temp_dir();
         tmp_path.push("cities_test.json");
@@ -70,4 +256,157 @@ mod tests {
         fs::remove_file(path_str)?;
         Ok(())
     }
+
+    #[test]
+    fn test_load_legacy_file_and_upgrade() -> Result<()> {
+        let mut tmp_path = std::env::temp_dir();
+        tmp_path.push("cities_legacy_test.json");
+        let path_str = tmp_path.to_str().unwrap();
+
+        fs::write(
+            path_str,
+            r#"{"Kyoto": {"lat": 35.02107, "lon": 135.75385}}"#,
+        )?;
+        let mut cities = Cities::load_from_file(path_str)?;
+        fs::remove_file(path_str)?;
+
+        assert_eq!(
+            cities.cities.get("Kyoto"),
+            Some(&city(35.02107, 135.75385, None))
+        );
+
+        let kyoto = city(35.02107, 135.75385, Some(1857910));
+        assert!(!cities.add("Kyoto".to_string(), kyoto.clone()));
+        assert!(cities.fill_metadata("Kyoto", kyoto.clone()));
+        assert_eq!(cities.cities.get("Kyoto"), Some(&kyoto));
+
+        assert!(!cities.fill_metadata("Kyoto", city(0.0, 0.0, Some(1))));
+        assert!(!cities.fill_metadata("Osaka", city(0.0, 0.0, Some(1))));
+        assert_eq!(cities.cities.get("Kyoto"), Some(&kyoto));
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_visits() -> Result<()> {
+        let mut cities = Cities::new();
+        cities.add(
+            "Kyoto".to_string(),
+            city(35.02107, 135.75385, Some(1857910)),
+        );
+
+        let spring = Visit {
+            from: date("2024-05-03"),
+            to: Some(date("2024-05-06")),
+            note: Some("Golden Week".to_string()),
+        };
+        let winter = Visit {
+            from: date("2023-12-28"),
+            to: None,
+            note: None,
+        };
+
+        assert!(cities.add_visit("Kyoto", spring.clone()));
+        assert!(cities.add_visit("Kyoto", winter.clone()));
+        assert!(!cities.add_visit("Kyoto", spring.clone()));
+        assert!(!cities.add_visit("Osaka", winter.clone()));
+        assert_eq!(
+            cities.cities["Kyoto"].visits,
+            vec![winter.clone(), spring.clone()]
+        );
+
+        assert_eq!(winter.to_string(), "2023-12-28");
+        assert_eq!(spring.to_string(), "2024-05-03 – 2024-05-06 (Golden Week)");
+
+        let json = serde_json::to_string(&cities.cities["Kyoto"])?;
+        assert!(json.contains(r#""visits":[{"from":"2023-12-28"},{"from":"2024-05-03","to":"2024-05-06","note":"Golden Week"}]"#));
+
+        // upgrading a legacy entry keeps the visits recorded on it
+        cities.add("Osaka".to_string(), city(34.69379, 135.50107, None));
+        cities.add_visit("Osaka", winter.clone());
+        assert!(cities.fill_metadata("Osaka", city(34.69379, 135.50107, Some(1853909))));
+        assert_eq!(cities.cities["Osaka"].visits, vec![winter]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge() {
+        let visit = |from: &str| Visit {
+            from: date(from),
+            to: None,
+            note: None,
+        };
+        let mut czechia = Cities::new();
+        czechia.add(
+            "Prague".to_string(),
+            City {
+                visits: vec![visit("2019-04-20")],
+                ..city(50.08804, 14.42076, Some(3067696))
+            },
+        );
+        czechia.add("Brno".to_string(), city(49.19522, 16.60796, None));
+
+        let mut czech = Cities::new();
+        czech.add(
+            "Praha".to_string(),
+            City {
+                visits: vec![visit("2019-04-20"), visit("2022-08-01")],
+                ..city(50.08804, 14.42076, Some(3067696))
+            },
+        );
+        czech.add(
+            "Brno".to_string(),
+            City {
+                visits: vec![visit("2021-03-02")],
+                ..city(49.19522, 16.60796, Some(3078610))
+            },
+        );
+        czech.add(
+            "Olomouc".to_string(),
+            city(49.59552, 17.25175, Some(3069011)),
+        );
+
+        czechia.merge(czech);
+        assert_eq!(
+            czechia.cities.keys().collect::<Vec<_>>(),
+            ["Brno", "Olomouc", "Prague"]
+        );
+        assert_eq!(
+            czechia.cities["Prague"].visits,
+            [visit("2019-04-20"), visit("2022-08-01")]
+        );
+        assert_eq!(czechia.cities["Brno"].geonameid, Some(3078610));
+        assert_eq!(czechia.cities["Brno"].visits, [visit("2021-03-02")]);
+    }
+
+    #[test]
+    fn test_remove_ignores_case() {
+        let mut cities = Cities::new();
+        cities.add(
+            "Frankfurt am Main".to_string(),
+            city(50.11552, 8.68417, None),
+        );
+
+        assert_eq!(
+            cities.remove("frankfurt AM main"),
+            Some("Frankfurt am Main".to_string())
+        );
+        assert_eq!(cities.remove("Frankfurt am Main"), None);
+        assert!(cities.is_empty());
+    }
+
+    #[test]
+    fn test_parse_coordinates() {
+        let parsed: Coordinates = "39.47, -0.37".parse().unwrap();
+        assert_eq!(
+            parsed,
+            Coordinates {
+                lat: 39.47,
+                lon: -0.37
+            }
+        );
+
+        assert!("39.47".parse::<Coordinates>().is_err());
+        assert!("north,east".parse::<Coordinates>().is_err());
+        assert!("91,0".parse::<Coordinates>().is_err());
+    }
 }
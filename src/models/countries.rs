@@ -1,33 +1,117 @@
+use crate::models::cities::Visit;
 use anyhow::Result;
-use serde::Deserialize;
-use std::{collections::BTreeSet, fs};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+};
 
-#[derive(Debug, Deserialize)]
+/// A visited country, stored under its ISO alpha-2 code.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Country {
+    /// Display name, as GeoNames spells it.
+    pub name: String,
+    /// File stem of the country's outline in `countries-geo/` for the map.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub outline: Option<String>,
+    /// Stays recorded for the country itself, e.g. when passing through
+    /// without stopping in a city.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub visits: Vec<Visit>,
+}
+
+#[derive(Debug, Default)]
 pub struct Countries {
-    countries: BTreeSet<String>,
+    countries: BTreeMap<String, Country>,
+}
+
+/// `countries.json` as written before countries were keyed by ISO code: a
+/// list of lowercase, underscored names.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredCountries {
+    ByIso(BTreeMap<String, Country>),
+    Legacy(BTreeSet<String>),
 }
 
 impl Countries {
+    pub fn new() -> Self {
+        Self::default()
+    }
     pub fn load_from_file(path: &str) -> Result<Self> {
         let content = fs::read_to_string(path)?;
-        let countries: BTreeSet<String> = serde_json::from_str(&content)?;
-        Ok(Self { countries })
+        match serde_json::from_str(&content)? {
+            StoredCountries::ByIso(countries) => Ok(Self { countries }),
+            StoredCountries::Legacy(_) => {
+                anyhow::bail!(
+                    "{path} lists countries by name, run `migrate` to key them by ISO code"
+                )
+            }
+        }
+    }
+    /// Reads the names of a `countries.json` in the format before ISO codes,
+    /// `None` when it is already keyed by ISO code.
+    pub fn load_legacy(path: &str) -> Result<Option<BTreeSet<String>>> {
+        let content = fs::read_to_string(path)?;
+        match serde_json::from_str(&content)? {
+            StoredCountries::ByIso(_) => Ok(None),
+            StoredCountries::Legacy(names) => Ok(Some(names)),
+        }
     }
     pub fn save_to_file(&self, path: &str) -> Result<()> {
         let json = serde_json::to_string_pretty(&self.countries)?;
         fs::write(path, json)?;
         Ok(())
     }
-    pub fn add(&mut self, country: &str) -> bool {
-        self.countries.insert(country.to_string())
+    /// Adds a country unless its ISO code is already recorded.
+    pub fn add(&mut self, iso: &str, country: Country) -> bool {
+        if self.countries.contains_key(iso) {
+            return false;
+        }
+        self.countries.insert(iso.to_string(), country);
+        true
+    }
+    /// Records a visit to a stored country, keeping visits in date order.
+    /// Returns false if the country is unknown or the same visit is already recorded.
+    pub fn add_visit(&mut self, iso: &str, visit: Visit) -> bool {
+        let Some(country) = self.countries.get_mut(iso) else {
+            return false;
+        };
+        if country.visits.contains(&visit) {
+            return false;
+        }
+        country.visits.push(visit);
+        country.visits.sort();
+        true
+    }
+    pub fn contains(&self, iso: &str) -> bool {
+        self.countries.contains_key(iso)
+    }
+    pub fn remove(&mut self, iso: &str) -> bool {
+        self.countries.remove(iso).is_some()
+    }
+    /// Countries by ISO code, in code order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Country)> {
+        self.countries
+            .iter()
+            .map(|(iso, country)| (iso.as_str(), country))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use chrono::NaiveDate;
     use std::{env, fs};
 
+    fn country(name: &str) -> Country {
+        Country {
+            name: name.to_string(),
+            outline: Some(name.to_lowercase()),
+            visits: Vec::new(),
+        }
+    }
+
     #[test]
     fn test_countries() -> Result<()> {
         let temp_dir = env::temp_dir();
@@ -39,22 +123,56 @@ mod tests {
             fs::remove_file(&temp_file)?;
         }
 
-        let mut countries = Countries {
-            countries: Default::default(),
+        let mut countries = Countries::new();
+        assert!(countries.add("DE", country("Germany")));
+        assert!(countries.add("FR", country("France")));
+        assert!(countries.add("JP", country("Japan")));
+        assert!(!countries.add("DE", country("Deutschland")));
+
+        let visit = Visit {
+            from: NaiveDate::from_ymd_opt(2024, 7, 1).unwrap(),
+            to: None,
+            note: Some("transit".to_string()),
         };
-        assert!(countries.add("Germany"));
-        assert!(countries.add("France"));
-        assert!(countries.add("Japan"));
+        assert!(countries.add_visit("DE", visit.clone()));
+        assert!(!countries.add_visit("DE", visit.clone()));
+        assert!(!countries.add_visit("IT", visit.clone()));
 
         countries.save_to_file(temp_file_str)?;
+        assert_eq!(Countries::load_legacy(temp_file_str)?, None);
+
+        let mut loaded = Countries::load_from_file(temp_file_str)?;
+        assert_eq!(loaded.countries["DE"].name, "Germany");
+        assert_eq!(loaded.countries["DE"].visits, [visit]);
+        assert!(loaded.contains("DE"));
+        assert!(loaded.countries.contains_key("FR"));
+        assert!(loaded.countries.contains_key("JP"));
 
-        let loaded = Countries::load_from_file(temp_file_str)?;
-        assert!(loaded.countries.contains("Germany"));
-        assert!(loaded.countries.contains("France"));
-        assert!(loaded.countries.contains("Japan"));
+        assert!(loaded.remove("FR"));
+        assert!(!loaded.remove("FR"));
+        assert_eq!(
+            loaded.iter().map(|(iso, _)| iso).collect::<Vec<_>>(),
+            ["DE", "JP"]
+        );
 
         fs::remove_file(temp_file)?;
 
         Ok(())
     }
+
+    #[test]
+    fn test_legacy_countries() -> Result<()> {
+        let temp_file = env::temp_dir().join("test_legacy_countries.json");
+        let temp_file_str = temp_file.to_str().unwrap();
+        fs::write(&temp_file, r#"["czech", "czechia", "the_netherlands"]"#)?;
+
+        let err = Countries::load_from_file(temp_file_str).unwrap_err();
+        assert!(err.to_string().contains("migrate"));
+        let names = Countries::load_legacy(temp_file_str)?.unwrap();
+        assert_eq!(names.len(), 3);
+        assert!(names.contains("the_netherlands"));
+
+        fs::remove_file(temp_file)?;
+        Ok(())
+    }
 }
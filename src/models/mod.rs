@@ -1,2 +1,3 @@
 pub(crate) mod cities;
 pub(crate) mod countries;
+pub(crate) mod summits;
@@ -0,0 +1,190 @@
+use anyhow::Result;
+use chrono::NaiveDate;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{Map, Value};
+use std::fs;
+
+/// Visited summits, stored as a GeoJSON `FeatureCollection` of points.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Summits {
+    #[serde(rename = "type")]
+    kind: String,
+    pub features: Vec<Feature>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Feature {
+    #[serde(rename = "type")]
+    kind: String,
+    pub properties: Summit,
+    pub geometry: Point,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Summit {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub elevation: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub date: Option<NaiveDate>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub geonameid: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub country_code: Option<String>,
+    /// Properties added by hand, kept as they are.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Point {
+    #[serde(rename = "type")]
+    kind: String,
+    /// GeoJSON order: longitude, latitude and optionally elevation.
+    #[serde(deserialize_with = "deserialize_position")]
+    pub coordinates: Vec<f64>,
+}
+
+impl Point {
+    pub fn new(lat: f64, lon: f64) -> Self {
+        Self {
+            kind: "Point".to_string(),
+            coordinates: vec![lon, lat],
+        }
+    }
+
+    pub fn lat_lon(&self) -> (f64, f64) {
+        (self.coordinates[1], self.coordinates[0])
+    }
+}
+
+fn deserialize_position<'de, D>(deserializer: D) -> Result<Vec<f64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let position = Vec::<f64>::deserialize(deserializer)?;
+    if !(2..=3).contains(&position.len()) {
+        return Err(serde::de::Error::invalid_length(
+            position.len(),
+            &"a longitude, a latitude and an optional elevation",
+        ));
+    }
+    Ok(position)
+}
+
+impl Summits {
+    pub fn new() -> Self {
+        Self {
+            kind: "FeatureCollection".to_string(),
+            features: Vec::new(),
+        }
+    }
+
+    /// Adds a summit unless the same GeoNames place, or a hand-written entry with
+    /// the same name, is already recorded.
+    pub fn add(&mut self, summit: Summit, point: Point) -> bool {
+        if self.features.iter().any(|f| {
+            f.properties.name == summit.name
+                && (f.properties.geonameid.is_none() || f.properties.geonameid == summit.geonameid)
+        }) {
+            return false;
+        }
+
+        self.features.push(Feature {
+            kind: "Feature".to_string(),
+            properties: summit,
+            geometry: point,
+        });
+        true
+    }
+
+    pub fn save_to_file(&self, path: &str) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(path, json)?;
+        Ok(())
+    }
+
+    pub fn load_from_file(path: &str) -> Result<Self> {
+        let content = fs::read_to_string(path)?;
+        let summits: Summits = serde_json::from_str(&content)?;
+        Ok(summits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, path::PathBuf};
+
+    fn summit(name: &str, geonameid: Option<i64>) -> Summit {
+        Summit {
+            name: name.to_string(),
+            elevation: Some(4478),
+            date: NaiveDate::from_ymd_opt(2023, 8, 15),
+            geonameid,
+            country_code: Some("CH".to_string()),
+            extra: Map::new(),
+        }
+    }
+
+    #[test]
+    fn test_load_docs_summits() -> Result<()> {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("docs/summits.json");
+        let summits = Summits::load_from_file(path.to_str().unwrap())?;
+
+        assert!(!summits.features.is_empty());
+        assert!(summits.features.iter().all(|f| f.geometry.kind == "Point"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_and_save_load() -> Result<()> {
+        let mut summits = Summits::new();
+        let matterhorn = Point::new(45.97639, 7.65861);
+
+        assert!(summits.add(summit("Matterhorn", Some(2659436)), matterhorn.clone()));
+        assert!(!summits.add(summit("Matterhorn", Some(2659436)), matterhorn.clone()));
+        assert!(summits.add(summit("Matterhorn", Some(1)), Point::new(0.0, 0.0)));
+
+        let temp_file = env::temp_dir().join("summits_test.json");
+        let temp_file_str = temp_file.to_str().unwrap();
+        summits.save_to_file(temp_file_str)?;
+
+        let json = fs::read_to_string(&temp_file)?;
+        assert!(json.contains(r#""type": "FeatureCollection""#));
+        assert!(json.contains(r#""date": "2023-08-15""#));
+
+        let loaded = Summits::load_from_file(temp_file_str)?;
+        assert_eq!(loaded.features, summits.features);
+        assert_eq!(loaded.features[0].geometry.coordinates, [7.65861, 45.97639]);
+
+        fs::remove_file(temp_file)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_keep_hand_written_fields() -> Result<()> {
+        let json = r#"{
+            "type": "FeatureCollection",
+            "features": [{
+                "type": "Feature",
+                "properties": { "name": "Dom", "elevation": 4545, "route": "Festi hut", "with": ["Anna"] },
+                "geometry": { "type": "Point", "coordinates": [7.85889, 46.09389, 4545.0] }
+            }]
+        }"#;
+        let summits: Summits = serde_json::from_str(json)?;
+        let dom = &summits.features[0];
+        assert_eq!(dom.properties.extra["route"], "Festi hut");
+        assert_eq!(dom.geometry.lat_lon(), (46.09389, 7.85889));
+
+        let saved: Value = serde_json::from_str(&serde_json::to_string(&summits)?)?;
+        let feature = &saved["features"][0];
+        assert_eq!(feature["properties"]["route"], "Festi hut");
+        assert_eq!(feature["properties"]["with"][0], "Anna");
+        assert_eq!(feature["geometry"]["coordinates"][2], 4545.0);
+
+        let invalid = json.replace("[7.85889, 46.09389, 4545.0]", "[7.85889]");
+        assert!(serde_json::from_str::<Summits>(&invalid).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,126 @@
+//! Rendering of command results for the terminal or for other tools.
+
+use clap::ValueEnum;
+use std::fmt::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Aligned columns
+    Table,
+    Json,
+    Markdown,
+    /// Comma-separated values with a header row
+    Csv,
+}
+
+/// Rows of text cells under a header, rendered as aligned columns or as a
+/// Markdown table.
+pub struct Table {
+    headers: Vec<String>,
+    rows: Vec<Vec<String>>,
+    /// Columns aligned to the right, e.g. numbers.
+    right: Vec<bool>,
+}
+
+impl Table {
+    pub fn new(headers: &[&str]) -> Self {
+        Self {
+            headers: headers.iter().map(|h| h.to_string()).collect(),
+            rows: Vec::new(),
+            right: vec![false; headers.len()],
+        }
+    }
+
+    /// Aligns the given columns to the right.
+    pub fn align_right(mut self, columns: &[usize]) -> Self {
+        for &column in columns {
+            self.right[column] = true;
+        }
+        self
+    }
+
+    pub fn push(&mut self, row: Vec<String>) {
+        self.rows.push(row);
+    }
+
+    pub fn to_columns(&self) -> String {
+        let widths = self.widths();
+        let mut out = String::new();
+        for row in std::iter::once(&self.headers).chain(&self.rows) {
+            let cells: Vec<String> = row
+                .iter()
+                .enumerate()
+                .map(|(i, cell)| self.pad(cell, widths[i], self.right[i]))
+                .collect();
+            writeln!(out, "{}", cells.join("  ").trim_end()).unwrap();
+        }
+        out
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "| {} |", self.headers.join(" | ")).unwrap();
+        let separators: Vec<&str> = self
+            .right
+            .iter()
+            .map(|right| if *right { "---:" } else { "---" })
+            .collect();
+        writeln!(out, "| {} |", separators.join(" | ")).unwrap();
+        for row in &self.rows {
+            let cells: Vec<String> = row.iter().map(|cell| cell.replace('|', "\\|")).collect();
+            writeln!(out, "| {} |", cells.join(" | ")).unwrap();
+        }
+        out
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        for row in std::iter::once(&self.headers).chain(&self.rows) {
+            writer.write_record(row).unwrap();
+        }
+        String::from_utf8(writer.into_inner().unwrap()).unwrap()
+    }
+
+    fn widths(&self) -> Vec<usize> {
+        let mut widths: Vec<usize> = self.headers.iter().map(|h| h.chars().count()).collect();
+        for row in &self.rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+        widths
+    }
+
+    fn pad(&self, cell: &str, width: usize, right: bool) -> String {
+        let fill = " ".repeat(width.saturating_sub(cell.chars().count()));
+        if right {
+            format!("{fill}{cell}")
+        } else {
+            format!("{cell}{fill}")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_table() {
+        let mut table = Table::new(&["Country", "Cities"]).align_right(&[1]);
+        table.push(vec!["spain".to_string(), "12".to_string()]);
+        table.push(vec!["côte_d'ivoire".to_string(), "3".to_string()]);
+
+        assert_eq!(
+            table.to_columns(),
+            "Country        Cities\nspain              12\ncôte_d'ivoire       3\n"
+        );
+        assert_eq!(
+            table.to_markdown(),
+            "| Country | Cities |\n| --- | ---: |\n| spain | 12 |\n| côte_d'ivoire | 3 |\n"
+        );
+        table.push(vec!["Bosnia, Herzegovina".to_string(), "1".to_string()]);
+        assert!(table.to_csv().starts_with("Country,Cities\nspain,12\n"));
+        assert!(table.to_csv().ends_with("\"Bosnia, Herzegovina\",1\n"));
+    }
+}
@@ -0,0 +1,37 @@
+use anyhow::Result;
+use std::io::{self, Write};
+
+pub(crate) fn confirm(question: &str) -> Result<bool> {
+    print!("{question} [y/N] ");
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Lets the user pick one of `options` by number. An empty answer skips the choice.
+pub(crate) fn choose(question: &str, options: &[String]) -> Result<Option<usize>> {
+    println!("{question}");
+    for (i, option) in options.iter().enumerate() {
+        println!("  {}) {option}", i + 1);
+    }
+
+    loop {
+        print!("Choice [1-{}, empty to skip]: ", options.len());
+        io::stdout().flush()?;
+
+        let mut answer = String::new();
+        if io::stdin().read_line(&mut answer)? == 0 {
+            return Ok(None);
+        }
+        let answer = answer.trim();
+        if answer.is_empty() {
+            return Ok(None);
+        }
+        match answer.parse::<usize>() {
+            Ok(n) if (1..=options.len()).contains(&n) => return Ok(Some(n - 1)),
+            _ => println!("Please enter a number between 1 and {}", options.len()),
+        }
+    }
+}
@@ -0,0 +1,570 @@
+use crate::config::Strava as StravaConfig;
+use crate::geo::densify;
+use crate::gpx::Track;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration as ChronoDuration, Timelike, Utc};
+use reqwest::header::RETRY_AFTER;
+use reqwest::{RequestBuilder, Response, StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+const SCOPE: &str = "read,activity:read_all";
+const REDIRECT_PATH: &str = "/exchange_token";
+const PER_PAGE: usize = 100;
+/// How often a rate limited request is retried before giving up.
+const MAX_RETRIES: u32 = 3;
+/// Refresh tokens that expire within this many seconds.
+const EXPIRY_MARGIN_SECS: i64 = 300;
+/// Longest request line the redirect listener accepts.
+const MAX_REQUEST_LINE: u64 = 8192;
+/// How long the redirect listener waits for a request line.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Tokens of an authorized athlete and how far activities have been synced.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Session {
+    pub access_token: String,
+    pub refresh_token: String,
+    /// Unix time the access token expires at.
+    pub expires_at: i64,
+    /// Start of the newest activity handled by a sync.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub synced_until: Option<DateTime<Utc>>,
+}
+
+impl Session {
+    pub fn load<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path).with_context(|| {
+            format!(
+                "No Strava session in {}, run strava-login first",
+                path.display()
+            )
+        })?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    /// Writes the session readable by the owner only, as it holds the tokens,
+    /// replacing the previous file in one step.
+    pub fn save<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let json = serde_json::to_string_pretty(self)?;
+        let tmp_file = path.with_extension("tmp");
+
+        // a leftover of an interrupted save would keep its permissions
+        if tmp_file.exists() {
+            fs::remove_file(&tmp_file)?;
+        }
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        let mut file = options
+            .open(&tmp_file)
+            .with_context(|| format!("Failed to write {}", tmp_file.display()))?;
+        file.write_all(json.as_bytes())?;
+        file.sync_all()?;
+        drop(file);
+
+        fs::rename(&tmp_file, path)?;
+        Ok(())
+    }
+
+    fn is_expiring(&self) -> bool {
+        self.expires_at - EXPIRY_MARGIN_SECS <= Utc::now().timestamp()
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    refresh_token: String,
+    expires_at: i64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Activity {
+    pub id: u64,
+    pub name: String,
+    #[serde(default)]
+    pub sport_type: Option<String>,
+    pub start_date: DateTime<Utc>,
+    /// Seconds from start to finish, pauses included.
+    #[serde(default)]
+    pub elapsed_time: i64,
+    #[serde(default)]
+    pub map: ActivityMap,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct ActivityMap {
+    #[serde(default)]
+    pub summary_polyline: Option<String>,
+}
+
+impl Activity {
+    /// The route of the activity with points at most `spacing_km` apart, or
+    /// `None` for activities recorded without GPS.
+    pub fn track(&self, spacing_km: f64) -> Result<Option<Track>> {
+        let Some(polyline) = self.map.summary_polyline.as_deref() else {
+            return Ok(None);
+        };
+        let points = decode_polyline(polyline)
+            .with_context(|| format!("Invalid route of activity {}", self.id))?;
+        if points.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Track {
+            points: densify(&points, spacing_km),
+            start: Some(self.start_date),
+            end: Some(self.start_date + ChronoDuration::seconds(self.elapsed_time)),
+        }))
+    }
+}
+
+/// Client for the OAuth endpoints and the activities API.
+pub struct Client<'a> {
+    http: reqwest::Client,
+    config: &'a StravaConfig,
+}
+
+impl<'a> Client<'a> {
+    pub fn new(config: &'a StravaConfig) -> Result<Self> {
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(60))
+            .build()
+            .context("Failed to build HTTP client")?;
+        Ok(Self { http, config })
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{path}", self.config.base_url.trim_end_matches('/'))
+    }
+
+    fn redirect_uri(&self) -> String {
+        format!(
+            "http://localhost:{}{REDIRECT_PATH}",
+            self.config.redirect_port
+        )
+    }
+
+    pub fn authorize_url(&self, state: &str) -> Result<Url> {
+        let url = Url::parse_with_params(
+            &self.url("/oauth/authorize"),
+            &[
+                ("client_id", self.config.client_id.as_str()),
+                ("response_type", "code"),
+                ("redirect_uri", &self.redirect_uri()),
+                ("approval_prompt", "auto"),
+                ("scope", SCOPE),
+                ("state", state),
+            ],
+        )?;
+        Ok(url)
+    }
+
+    /// Waits for the browser to be redirected back after the athlete
+    /// authorized the application and returns the authorization code.
+    pub async fn receive_code(&self, state: &str) -> Result<String> {
+        let listener = TcpListener::bind(("127.0.0.1", self.config.redirect_port))
+            .await
+            .with_context(|| {
+                format!(
+                    "Failed to listen on port {} for the Strava redirect",
+                    self.config.redirect_port
+                )
+            })?;
+
+        wait_for_code(&listener, state).await
+    }
+
+    pub async fn exchange_code(&self, code: &str) -> Result<Session> {
+        let token = self
+            .request_token(&[("grant_type", "authorization_code"), ("code", code)])
+            .await?;
+        Ok(Session {
+            access_token: token.access_token,
+            refresh_token: token.refresh_token,
+            expires_at: token.expires_at,
+            synced_until: None,
+        })
+    }
+
+    /// Refreshes the access token when it is about to expire. Returns whether
+    /// the session changed and needs to be stored again.
+    pub async fn refresh(&self, session: &mut Session) -> Result<bool> {
+        if !session.is_expiring() {
+            return Ok(false);
+        }
+        let token = self
+            .request_token(&[
+                ("grant_type", "refresh_token"),
+                ("refresh_token", &session.refresh_token),
+            ])
+            .await?;
+        session.access_token = token.access_token;
+        session.refresh_token = token.refresh_token;
+        session.expires_at = token.expires_at;
+        Ok(true)
+    }
+
+    async fn request_token(&self, grant: &[(&str, &str)]) -> Result<TokenResponse> {
+        let mut form = vec![
+            ("client_id", self.config.client_id.as_str()),
+            ("client_secret", self.config.client_secret.as_str()),
+        ];
+        form.extend_from_slice(grant);
+
+        let url = self.url("/oauth/token");
+        let response = self
+            .send(|| self.http.post(&url).form(&form))
+            .await
+            .context("Failed to request a Strava access token")?;
+        Ok(response.json().await?)
+    }
+
+    /// All activities that started after `after`, oldest first.
+    pub async fn activities(
+        &self,
+        session: &Session,
+        after: Option<DateTime<Utc>>,
+    ) -> Result<Vec<Activity>> {
+        let url = self.url("/api/v3/athlete/activities");
+        let after = after.map_or(0, |after| after.timestamp());
+        let mut activities = Vec::new();
+
+        for page in 1.. {
+            let query = [
+                ("after", after.to_string()),
+                ("page", page.to_string()),
+                ("per_page", PER_PAGE.to_string()),
+            ];
+            let response = self
+                .send(|| {
+                    self.http
+                        .get(&url)
+                        .bearer_auth(&session.access_token)
+                        .query(&query)
+                })
+                .await
+                .context("Failed to fetch Strava activities")?;
+            let batch: Vec<Activity> = response.json().await?;
+            let last_page = batch.len() < PER_PAGE;
+            activities.extend(batch);
+            if last_page {
+                break;
+            }
+        }
+
+        activities.sort_by_key(|activity| activity.start_date);
+        Ok(activities)
+    }
+
+    /// Sends a request, waiting and retrying while Strava rate limits us.
+    async fn send<F>(&self, request: F) -> Result<Response>
+    where
+        F: Fn() -> RequestBuilder,
+    {
+        let mut retries = 0;
+        loop {
+            let response = request().send().await?;
+            match response.status() {
+                status if status.is_success() => return Ok(response),
+                StatusCode::TOO_MANY_REQUESTS if retries < MAX_RETRIES => {
+                    retries += 1;
+                    let wait = rate_limit_wait(&response, Utc::now());
+                    println!("Strava rate limit reached, retrying in {}s", wait.as_secs());
+                    tokio::time::sleep(wait).await;
+                }
+                StatusCode::UNAUTHORIZED => {
+                    anyhow::bail!("Strava rejected the credentials, run strava-login again")
+                }
+                status => {
+                    let body = response.text().await.unwrap_or_default();
+                    anyhow::bail!("Strava answered {status}: {body}");
+                }
+            }
+        }
+    }
+}
+
+/// Strava does not send `Retry-After`, its limits reset every quarter hour,
+/// but a server that does send it is taken at its word.
+fn rate_limit_wait(response: &Response, now: DateTime<Utc>) -> Duration {
+    if let Some(secs) = response
+        .headers()
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse().ok())
+    {
+        return Duration::from_secs(secs);
+    }
+    let into_quarter = (now.minute() % 15) * 60 + now.second();
+    Duration::from_secs(u64::from(15 * 60 - into_quarter) + 1)
+}
+
+/// A value to tie the redirect to the authorization request it answers. It
+/// comes from the system's random source, so another page cannot guess it
+/// and send its own code to the local listener.
+pub fn new_state() -> Result<String> {
+    let mut bytes = [0u8; 16];
+    getrandom::fill(&mut bytes)
+        .map_err(|err| anyhow::anyhow!("Failed to generate the state: {err}"))?;
+    Ok(bytes.iter().map(|b| format!("{b:02x}")).collect())
+}
+
+/// Serves the local redirect listener until a request carrying the
+/// authorization code for `state` arrives. Other requests, including ones
+/// with a wrong or missing state, are answered and the wait goes on.
+async fn wait_for_code(listener: &TcpListener, state: &str) -> Result<String> {
+    loop {
+        // a stray connection that fails must not end the login
+        let Ok((mut stream, _)) = listener.accept().await else {
+            continue;
+        };
+        let read = tokio::time::timeout(REQUEST_TIMEOUT, read_request_target(&mut stream));
+        let Ok(Ok(target)) = read.await else {
+            continue;
+        };
+
+        let (status, code) = match target.as_deref().map(|t| authorization_code(t, state)) {
+            None => ("400 Bad Request", None),
+            Some(Redirect::Other) => ("404 Not Found", None),
+            Some(Redirect::ForeignState) => ("400 Bad Request", None),
+            Some(Redirect::Code(code)) => ("200 OK", Some(code)),
+        };
+        let Some(code) = code else {
+            let response = format!("HTTP/1.1 {status}\r\nConnection: close\r\n\r\n");
+            let _ = stream.write_all(response.as_bytes()).await;
+            continue;
+        };
+
+        let message = match &code {
+            Ok(_) => "Authorized, you can close this window.".to_string(),
+            Err(err) => format!("Authorization failed: {err}"),
+        };
+        let response = format!(
+            "HTTP/1.1 {status}\r\nContent-Type: text/plain; charset=utf-8\r\nConnection: close\r\n\r\n{message}"
+        );
+        // the code is good even if the browser went away in the meantime
+        let _ = stream.write_all(response.as_bytes()).await;
+        return code;
+    }
+}
+
+/// Reads the request line and returns its target, or `None` when the line
+/// is malformed or longer than [`MAX_REQUEST_LINE`].
+async fn read_request_target(stream: &mut TcpStream) -> std::io::Result<Option<String>> {
+    let mut line = String::new();
+    BufReader::new(stream.take(MAX_REQUEST_LINE))
+        .read_line(&mut line)
+        .await?;
+    if !line.ends_with('\n') {
+        return Ok(None);
+    }
+    Ok(line.split_whitespace().nth(1).map(str::to_string))
+}
+
+/// What a request to the redirect listener turned out to be.
+#[derive(Debug)]
+enum Redirect {
+    /// Not the redirect, e.g. `/favicon.ico`.
+    Other,
+    /// The redirect of another login, or a forged one.
+    ForeignState,
+    Code(Result<String>),
+}
+
+/// Extracts the authorization code from the request target the browser was
+/// redirected to.
+fn authorization_code(target: &str, state: &str) -> Redirect {
+    let Ok(url) = Url::parse("http://localhost").and_then(|base| base.join(target)) else {
+        return Redirect::Other;
+    };
+    if url.path() != REDIRECT_PATH {
+        return Redirect::Other;
+    }
+
+    let params: HashMap<String, String> = url.query_pairs().into_owned().collect();
+    if params.get("state").map(String::as_str) != Some(state) {
+        return Redirect::ForeignState;
+    }
+    if let Some(error) = params.get("error") {
+        return Redirect::Code(Err(anyhow::anyhow!(
+            "Strava authorization was denied: {error}"
+        )));
+    }
+    Redirect::Code(
+        params
+            .get("code")
+            .cloned()
+            .context("Strava redirect has no authorization code"),
+    )
+}
+
+/// Decodes an encoded polyline as used by Strava (precision 5) into
+/// `(lat, lon)` pairs.
+pub fn decode_polyline(encoded: &str) -> Result<Vec<(f64, f64)>> {
+    let mut bytes = encoded.bytes();
+    let mut points = Vec::new();
+    let (mut lat, mut lon) = (0i64, 0i64);
+
+    while let Some(d_lat) = next_value(&mut bytes)? {
+        let d_lon = next_value(&mut bytes)?.context("Polyline ends in the middle of a point")?;
+        lat += d_lat;
+        lon += d_lon;
+        points.push((lat as f64 / 1e5, lon as f64 / 1e5));
+    }
+    Ok(points)
+}
+
+fn next_value(bytes: &mut impl Iterator<Item = u8>) -> Result<Option<i64>> {
+    let mut value = 0i64;
+    let mut shift = 0;
+    loop {
+        let Some(byte) = bytes.next() else {
+            if shift == 0 {
+                return Ok(None);
+            }
+            anyhow::bail!("Polyline ends in the middle of a value");
+        };
+        if !(63..=126).contains(&byte) || shift > 60 {
+            anyhow::bail!("Invalid polyline character '{}'", byte as char);
+        }
+        let chunk = i64::from(byte - 63);
+        value |= (chunk & 0x1f) << shift;
+        shift += 5;
+        if chunk < 0x20 {
+            break;
+        }
+    }
+    Ok(Some(if value & 1 == 1 {
+        !(value >> 1)
+    } else {
+        value >> 1
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_polyline() -> Result<()> {
+        // example from Google's polyline algorithm documentation
+        let points = decode_polyline("_p~iF~ps|U_ulLnnqC_mqNvxq`@")?;
+        assert_eq!(
+            points,
+            vec![(38.5, -120.2), (40.7, -120.95), (43.252, -126.453)]
+        );
+
+        assert!(decode_polyline("")?.is_empty());
+        assert!(decode_polyline("_p~iF").is_err());
+        assert!(decode_polyline("_p~iF~ps|U_").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_authorization_code() -> Result<()> {
+        assert!(matches!(
+            authorization_code("/exchange_token?state=abc&code=123&scope=read", "abc"),
+            Redirect::Code(Ok(code)) if code == "123"
+        ));
+        assert!(matches!(
+            authorization_code("/favicon.ico", "abc"),
+            Redirect::Other
+        ));
+        assert!(matches!(
+            authorization_code("/exchange_token?state=xyz&code=123", "abc"),
+            Redirect::ForeignState
+        ));
+        assert!(matches!(
+            authorization_code("/exchange_token?error=access_denied", "abc"),
+            Redirect::ForeignState
+        ));
+        assert!(matches!(
+            authorization_code("/exchange_token?state=abc&error=access_denied", "abc"),
+            Redirect::Code(Err(_))
+        ));
+
+        let state = new_state()?;
+        assert_eq!(state.len(), 32);
+        assert!(state.bytes().all(|b| b.is_ascii_hexdigit()));
+        assert_ne!(state, new_state()?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_code() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let waiting = tokio::spawn(async move { wait_for_code(&listener, "abc").await });
+
+        async fn send(addr: std::net::SocketAddr, parts: &[&str]) -> Result<String> {
+            let mut stream = TcpStream::connect(addr).await?;
+            for part in parts {
+                stream.write_all(part.as_bytes()).await?;
+                stream.flush().await?;
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await?;
+            Ok(response)
+        }
+
+        // a stray connection that never sends anything
+        drop(TcpStream::connect(addr).await?);
+        let response = send(
+            addr,
+            &["GET /exchange_token?state=xyz&code=1 HTTP/1.1\r\n\r\n"],
+        );
+        assert!(response.await?.starts_with("HTTP/1.1 400 "));
+        let response = send(addr, &["GET /favicon.ico HTTP/1.1\r\n\r\n"]);
+        assert!(response.await?.starts_with("HTTP/1.1 404 "));
+        assert!(!waiting.is_finished());
+
+        // the request line may arrive in pieces
+        let parts = [
+            "GET /exchange_token?sta",
+            "te=abc&code=123 HTTP/1.1\r\n\r\n",
+        ];
+        assert!(send(addr, &parts).await?.starts_with("HTTP/1.1 200 "));
+        assert_eq!(waiting.await??, "123");
+        Ok(())
+    }
+
+    #[test]
+    fn test_save_session() -> Result<()> {
+        let path = std::env::temp_dir().join("test_strava_session.json");
+        let session = Session {
+            access_token: "access".to_string(),
+            refresh_token: "refresh".to_string(),
+            expires_at: 0,
+            synced_until: None,
+        };
+        session.save(&path)?;
+        session.save(&path)?;
+        assert_eq!(Session::load(&path)?.refresh_token, "refresh");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path)?.permissions().mode() & 0o777, 0o600);
+        }
+        fs::remove_file(path)?;
+        Ok(())
+    }
+}
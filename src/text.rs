@@ -0,0 +1,73 @@
+use unicode_normalization::UnicodeNormalization;
+use unicode_normalization::char::is_combining_mark;
+
+/// Strips accents and maps letters that do not decompose (ß, ø, ł, ...) to their
+/// usual Latin spelling, so "Zürich" and "Łódź" compare equal to "Zurich" and "Lodz".
+pub fn fold_diacritics(s: &str) -> String {
+    let mut folded = String::with_capacity(s.len());
+    for c in s.nfd().filter(|c| !is_combining_mark(*c)) {
+        match c {
+            'ß' => folded.push_str("ss"),
+            'æ' => folded.push_str("ae"),
+            'Æ' => folded.push_str("AE"),
+            'œ' => folded.push_str("oe"),
+            'Œ' => folded.push_str("OE"),
+            'ø' => folded.push('o'),
+            'Ø' => folded.push('O'),
+            'ł' => folded.push('l'),
+            'Ł' => folded.push('L'),
+            'đ' | 'ð' => folded.push('d'),
+            'Đ' | 'Ð' => folded.push('D'),
+            'ı' => folded.push('i'),
+            'þ' => folded.push_str("th"),
+            'Þ' => folded.push_str("TH"),
+            _ => folded.push(c),
+        }
+    }
+    folded
+}
+
+/// Number of single-character edits needed to turn `a` into `b`.
+pub fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fold_diacritics() {
+        assert_eq!(fold_diacritics("Zürich"), "Zurich");
+        assert_eq!(
+            fold_diacritics("Sant Julià de Lòria"),
+            "Sant Julia de Loria"
+        );
+        assert_eq!(fold_diacritics("Łódź"), "Lodz");
+        assert_eq!(fold_diacritics("Gießen"), "Giessen");
+        assert_eq!(fold_diacritics("Tromsø"), "Tromso");
+        assert_eq!(fold_diacritics("Москва"), "Москва");
+    }
+
+    #[test]
+    fn test_levenshtein() {
+        assert_eq!(levenshtein("", ""), 0);
+        assert_eq!(levenshtein("zurich", "zurich"), 0);
+        assert_eq!(levenshtein("zuirch", "zurich"), 2);
+        assert_eq!(levenshtein("munchen", "munich"), 3);
+        assert_eq!(levenshtein("", "abc"), 3);
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+    }
+}